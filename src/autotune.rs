@@ -0,0 +1,217 @@
+use crate::params::Param;
+use crate::pitch_detection::{Note, PitchTracker};
+use crate::pitch_shifter::{ratio_to_semitones, semitones_to_ratio};
+use crate::processor::{AudioProcessor, try_read};
+use crate::scale::Scale;
+use crate::smoothing::{Ramp, SmoothedParam};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Range of fundamentals corrected: a low bass voice to a high soprano.
+const VOICE_RANGE: (f32, f32) = (60.0, 1200.0);
+/// Pitch detections per second; each one can move the correction.
+const UPDATES_PER_SECOND: u32 = 200;
+/// Largest correction, in semitones either way: no note is further than
+/// half an octave from the nearest one in a scale.
+pub const MAX_CORRECTION: f32 = 6.0;
+/// Rate assumed until [`AudioProcessor::prepare`] is called.
+const DEFAULT_SAMPLE_RATE: u32 = 48_000;
+
+/// Live auto-tune settings, shared with the control thread.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AutoTuneSettings {
+    pub enabled: bool,
+    /// Notes the input is pulled to.
+    pub scale: Scale,
+    /// Time constant of the glide onto the corrected note; zero snaps
+    /// instantly for the robotic hard-tune sound.
+    pub retune_speed: Duration,
+    /// Share of the singer's own deviation from the note that is kept, from
+    /// 0 (fully corrected) to 1 (uncorrected), so vibrato and scoops survive.
+    pub humanize: f32,
+}
+
+impl Default for AutoTuneSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            scale: Scale::CHROMATIC,
+            retune_speed: Duration::from_millis(10),
+            humanize: 0.0,
+        }
+    }
+}
+
+/// Automatic pitch correction.
+///
+/// Tracks the pitch of the audio running through it and writes the factor
+/// that moves it onto the nearest note of the scale into `pitch_factor`,
+/// where a pitch shifter later in the chain picks it up. The audio itself
+/// passes through untouched. Any transposition set in `transpose` is applied
+/// before snapping, so the result still lands in key. While the input is
+/// unvoiced the last correction is held.
+pub struct AutoTune {
+    transpose: Param,
+    pitch_factor: Param,
+    settings: Arc<Mutex<AutoTuneSettings>>,
+    tracker: PitchTracker,
+    channels: usize,
+    // Last values read from the shared controls.
+    current: AutoTuneSettings,
+    transpose_semitones: f32,
+    // Correction in semitones, kept while the input is unvoiced.
+    correction: f32,
+    semitones: SmoothedParam,
+}
+
+impl AutoTune {
+    /// Reads the manual shift from `transpose` and writes the corrected
+    /// shift to `pitch_factor` on every block.
+    pub fn new(transpose: Param, pitch_factor: Param) -> Self {
+        let settings = AutoTuneSettings::default();
+        let semitones = ratio_to_semitones(transpose.get());
+        Self {
+            transpose,
+            pitch_factor,
+            settings: Arc::new(Mutex::new(settings)),
+            tracker: PitchTracker::with_range(VOICE_RANGE.0, VOICE_RANGE.1)
+                .with_update_rate(UPDATES_PER_SECOND),
+            channels: 1,
+            current: settings,
+            transpose_semitones: semitones,
+            correction: 0.0,
+            semitones: SmoothedParam::new(
+                semitones,
+                Ramp::Exponential(settings.retune_speed),
+                DEFAULT_SAMPLE_RATE,
+            ),
+        }
+    }
+
+    /// Starts from `settings` instead of the defaults.
+    pub fn with_settings(self, settings: AutoTuneSettings) -> Self {
+        *self.settings.lock().unwrap() = settings;
+        self
+    }
+
+    /// Handle for changing the settings while audio is running.
+    pub fn settings(&self) -> Arc<Mutex<AutoTuneSettings>> {
+        Arc::clone(&self.settings)
+    }
+
+    /// Correction, in semitones, that the next detection will glide towards.
+    fn update_correction(&mut self, settings: &AutoTuneSettings, transpose: f32) {
+        if !settings.enabled {
+            self.correction = 0.0;
+            return;
+        }
+        let Some(pitch) = self.tracker.pitch() else {
+            return;
+        };
+        let (note, cents) = Note::from_frequency(pitch.frequency);
+        let position = note.0 as f32 + cents / 100.0 + transpose;
+        if let Some(target) = settings.scale.nearest(position) {
+            let humanize = settings.humanize.clamp(0.0, 1.0);
+            self.correction = (target.0 as f32 - position) * (1.0 - humanize);
+        }
+    }
+}
+
+impl AudioProcessor for AutoTune {
+    fn prepare(&mut self, sample_rate: u32, channels: usize) {
+        self.channels = channels;
+        self.tracker.prepare(sample_rate, channels);
+        self.semitones.set_sample_rate(sample_rate);
+        self.reset();
+    }
+
+    fn process(&mut self, block: &mut [f32]) {
+        self.tracker.process(block);
+        if let Some(settings) = try_read(&self.settings) {
+            if settings.retune_speed != self.current.retune_speed {
+                self.semitones
+                    .set_ramp(Ramp::Exponential(settings.retune_speed));
+            }
+            self.current = settings;
+        }
+        self.transpose_semitones = ratio_to_semitones(self.transpose.get());
+
+        let settings = self.current;
+        let transpose = self.transpose_semitones;
+        self.update_correction(&settings, transpose);
+        let target = transpose + self.correction;
+        if target != self.semitones.target() {
+            self.semitones.set_target(target);
+        }
+        let semitones = self.semitones.skip(block.len() / self.channels);
+        self.pitch_factor.set(semitones_to_ratio(semitones));
+    }
+
+    fn reset(&mut self) {
+        self.tracker.reset();
+        self.correction = 0.0;
+        self.transpose_semitones = ratio_to_semitones(self.transpose.get());
+        self.semitones.reset(self.transpose_semitones);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pitch_shifter::PITCH_FACTOR;
+    use std::f32::consts::PI;
+
+    fn corrected_ratio(freq: f32, settings: AutoTuneSettings) -> f32 {
+        let pitch_factor = Param::new(PITCH_FACTOR);
+        let mut autotune =
+            AutoTune::new(Param::new(PITCH_FACTOR), pitch_factor.clone()).with_settings(settings);
+        autotune.prepare(48_000, 1);
+
+        let mut input: Vec<f32> = (0..24_000)
+            .map(|i| {
+                let phase = 2.0 * PI * freq * i as f32 / 48_000.0;
+                0.5 * phase.sin() + 0.2 * (2.0 * phase).sin()
+            })
+            .collect();
+        let original = input.clone();
+        for block in input.chunks_mut(256) {
+            autotune.process(block);
+        }
+        assert_eq!(input, original);
+        pitch_factor.get()
+    }
+
+    #[test]
+    fn pulls_input_onto_the_nearest_scale_note() {
+        let hard = AutoTuneSettings {
+            retune_speed: Duration::ZERO,
+            ..AutoTuneSettings::default()
+        };
+        let ratio = corrected_ratio(435.0, hard);
+        assert!((ratio - 440.0 / 435.0).abs() < 1e-3, "{ratio}");
+
+        // Between C#4 and D4: C# is out of C major, so it goes up to D.
+        let c_major = AutoTuneSettings {
+            scale: Scale::major(0),
+            ..hard
+        };
+        let ratio = corrected_ratio(280.0, c_major);
+        assert!(
+            (ratio - Note(62).frequency() / 280.0).abs() < 1e-3,
+            "{ratio}"
+        );
+
+        let humanized = AutoTuneSettings {
+            humanize: 0.5,
+            ..hard
+        };
+        let ratio = corrected_ratio(435.0, humanized);
+        assert!((ratio - (440.0_f32 / 435.0).sqrt()).abs() < 1e-3, "{ratio}");
+
+        let disabled = AutoTuneSettings {
+            enabled: false,
+            ..hard
+        };
+        assert_eq!(corrected_ratio(435.0, disabled), 1.0);
+    }
+}
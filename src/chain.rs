@@ -0,0 +1,524 @@
+use crate::processor::AudioProcessor;
+use anyhow::{Result, bail};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+/// Most effects a chain holds; its slots are allocated up front so edits
+/// made while it runs never allocate on the audio thread.
+pub const MAX_EFFECTS: usize = 32;
+/// Edits a [`ChainRemote`] can queue before the chain picks them up.
+const MAX_PENDING_COMMANDS: usize = 64;
+
+struct Slot {
+    name: String,
+    processor: Box<dyn AudioProcessor>,
+    bypassed: bool,
+}
+
+impl Slot {
+    /// Effects are reset when they come back so they don't replay stale audio.
+    fn set_bypassed(&mut self, bypassed: bool) {
+        if self.bypassed && !bypassed {
+            self.processor.reset();
+        }
+        self.bypassed = bypassed;
+    }
+}
+
+enum ChainCommand {
+    Insert(usize, Slot),
+    Remove(usize),
+    Move {
+        from: usize,
+        to: usize,
+    },
+    Bypass(usize, bool),
+    /// Puts the effect at `order[i]` in position `i` with bypass
+    /// `bypassed[i]`, for every position in one go.
+    Arrange {
+        order: [u8; MAX_EFFECTS],
+        bypassed: [bool; MAX_EFFECTS],
+    },
+}
+
+/// Stream format the chain was last prepared with, if any.
+///
+/// Only touched off the audio thread: the remote holds the lock while it
+/// prepares and queues an insert, and the chain holds it while it prepares,
+/// so every effect reaches the audio thread prepared for the current format.
+#[derive(Default)]
+struct SharedFormat(Mutex<Option<(u32, usize)>>);
+
+impl SharedFormat {
+    fn lock(&self) -> MutexGuard<'_, Option<(u32, usize)>> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Ordered list of named effects run one after another on the same block.
+///
+/// The chain is itself an [`AudioProcessor`], so it can be handed to
+/// [`StreamHost::start`](crate::StreamHost::start) or run offline. Once it
+/// has moved to the audio thread, a [`ChainRemote`] edits it between blocks.
+/// It holds at most [`MAX_EFFECTS`] effects.
+pub struct EffectChain {
+    slots: Vec<Slot>,
+    format: Arc<SharedFormat>,
+    commands: Option<Receiver<ChainCommand>>,
+    removed: Option<SyncSender<Slot>>,
+}
+
+impl EffectChain {
+    pub fn new() -> Self {
+        Self {
+            slots: Vec::with_capacity(MAX_EFFECTS),
+            format: Arc::default(),
+            commands: None,
+            removed: None,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    /// Names of the effects in processing order.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.slots.iter().map(|slot| slot.name.as_str())
+    }
+
+    /// Appends an effect to the end of the chain.
+    pub fn push(&mut self, name: &str, processor: impl AudioProcessor + 'static) {
+        self.insert(self.slots.len(), name, processor);
+    }
+
+    /// Inserts an effect before position `index`.
+    ///
+    /// Panics if `index > len` or the chain already holds [`MAX_EFFECTS`].
+    pub fn insert(&mut self, index: usize, name: &str, processor: impl AudioProcessor + 'static) {
+        assert!(
+            self.slots.len() < MAX_EFFECTS,
+            "an effect chain holds at most {MAX_EFFECTS} effects"
+        );
+        let mut processor: Box<dyn AudioProcessor> = Box::new(processor);
+        if let Some((sample_rate, channels)) = *self.format.lock() {
+            processor.prepare(sample_rate, channels);
+        }
+        self.slots.insert(
+            index,
+            Slot {
+                name: name.to_string(),
+                processor,
+                bypassed: false,
+            },
+        );
+    }
+
+    /// Removes and returns the effect at `index`.
+    ///
+    /// Panics if `index` is out of range.
+    pub fn remove(&mut self, index: usize) -> Box<dyn AudioProcessor> {
+        self.slots.remove(index).processor
+    }
+
+    /// Moves the effect at `from` so that it ends up at position `to`.
+    ///
+    /// Panics if either index is out of range.
+    pub fn move_effect(&mut self, from: usize, to: usize) {
+        let slot = self.slots.remove(from);
+        self.slots.insert(to, slot);
+    }
+
+    /// Skips the effect at `index` while `bypassed` is set. Effects are reset
+    /// when they come back so they don't replay stale audio.
+    ///
+    /// Panics if `index` is out of range.
+    pub fn set_bypassed(&mut self, index: usize, bypassed: bool) {
+        self.slots[index].set_bypassed(bypassed);
+    }
+
+    pub fn is_bypassed(&self, index: usize) -> bool {
+        self.slots[index].bypassed
+    }
+
+    /// Returns a handle for editing the chain after it has moved to the
+    /// audio thread. Creating a new remote disconnects the previous one.
+    pub fn remote(&mut self) -> ChainRemote {
+        // Bounded channels keep their buffers from the start, so neither
+        // sending nor receiving allocates or frees on the audio thread.
+        let (commands, command_rx) = mpsc::sync_channel(MAX_PENDING_COMMANDS);
+        let (removed_tx, removed) = mpsc::sync_channel(MAX_EFFECTS);
+        self.commands = Some(command_rx);
+        self.removed = Some(removed_tx);
+        ChainRemote {
+            commands,
+            removed,
+            format: Arc::clone(&self.format),
+            effects: self
+                .slots
+                .iter()
+                .map(|slot| (slot.name.clone(), slot.bypassed))
+                .collect(),
+        }
+    }
+
+    /// Applies edits queued by the remote.
+    fn apply_commands(&mut self) {
+        let Some(commands) = &self.commands else {
+            return;
+        };
+        while let Ok(command) = commands.try_recv() {
+            match command {
+                ChainCommand::Insert(index, slot) => self.slots.insert(index, slot),
+                ChainCommand::Remove(index) => {
+                    let slot = self.slots.remove(index);
+                    // Hand it back so it is freed off the audio thread. The
+                    // remote collects before every edit, so there is room
+                    // unless it has gone, in which case it is freed here.
+                    if let Some(removed) = &self.removed {
+                        let _ = removed.try_send(slot);
+                    }
+                }
+                ChainCommand::Move { from, to } => {
+                    let slot = self.slots.remove(from);
+                    self.slots.insert(to, slot);
+                }
+                ChainCommand::Bypass(index, bypassed) => {
+                    self.slots[index].set_bypassed(bypassed);
+                }
+                ChainCommand::Arrange { order, bypassed } => {
+                    // Original position of the effect now in each slot.
+                    let mut current: [u8; MAX_EFFECTS] = std::array::from_fn(|i| i as u8);
+                    for index in 0..self.slots.len() {
+                        let from = (index..self.slots.len())
+                            .find(|&slot| current[slot] == order[index])
+                            .unwrap();
+                        self.slots.swap(index, from);
+                        current.swap(index, from);
+                        self.slots[index].set_bypassed(bypassed[index]);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Default for EffectChain {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AudioProcessor for EffectChain {
+    fn prepare(&mut self, sample_rate: u32, channels: usize) {
+        let format = Arc::clone(&self.format);
+        let mut format = format.lock();
+        *format = Some((sample_rate, channels));
+        // Take in effects queued before now, which may not have been
+        // prepared for this format, so they are prepared here instead of
+        // on the audio thread.
+        self.apply_commands();
+        for slot in &mut self.slots {
+            slot.processor.prepare(sample_rate, channels);
+        }
+    }
+
+    fn process(&mut self, block: &mut [f32]) {
+        self.apply_commands();
+        for slot in &mut self.slots {
+            if !slot.bypassed {
+                slot.processor.process(block);
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        for slot in &mut self.slots {
+            slot.processor.reset();
+        }
+    }
+
+    fn latency(&self) -> usize {
+        self.slots
+            .iter()
+            .filter(|slot| !slot.bypassed)
+            .map(|slot| slot.processor.latency())
+            .sum()
+    }
+}
+
+/// Edits a running [`EffectChain`] from a control thread.
+///
+/// Changes are queued and take effect at the start of the chain's next
+/// block. The remote keeps its own copy of the effect list so it can
+/// validate indices and report the chain without touching the audio thread.
+pub struct ChainRemote {
+    commands: SyncSender<ChainCommand>,
+    removed: Receiver<Slot>,
+    format: Arc<SharedFormat>,
+    effects: Vec<(String, bool)>,
+}
+
+impl ChainRemote {
+    pub fn len(&self) -> usize {
+        self.effects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.effects.is_empty()
+    }
+
+    /// Names of the effects in processing order.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.effects.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// Position of the effect called `name`.
+    pub fn position(&self, name: &str) -> Option<usize> {
+        self.effects.iter().position(|(effect, _)| effect == name)
+    }
+
+    pub fn is_bypassed(&self, index: usize) -> bool {
+        self.effects[index].1
+    }
+
+    /// Appends an effect to the end of the chain.
+    pub fn push(&mut self, name: &str, processor: impl AudioProcessor + 'static) -> Result<()> {
+        self.insert(self.effects.len(), name, processor)
+    }
+
+    /// Inserts an effect before position `index`. The effect is prepared
+    /// here, on the calling thread, once the chain's format is known;
+    /// otherwise the chain prepares it when it is itself prepared.
+    pub fn insert(
+        &mut self,
+        index: usize,
+        name: &str,
+        processor: impl AudioProcessor + 'static,
+    ) -> Result<()> {
+        if index > self.effects.len() {
+            bail!("No position {} in a chain of {}", index, self.effects.len());
+        }
+        if self.effects.len() >= MAX_EFFECTS {
+            bail!("A chain holds at most {MAX_EFFECTS} effects");
+        }
+        let mut processor: Box<dyn AudioProcessor> = Box::new(processor);
+        let format = self.format.lock();
+        if let Some((sample_rate, channels)) = *format {
+            processor.prepare(sample_rate, channels);
+        }
+        let slot = Slot {
+            name: name.to_string(),
+            processor,
+            bypassed: false,
+        };
+        self.send(ChainCommand::Insert(index, slot))?;
+        drop(format);
+        self.effects.insert(index, (name.to_string(), false));
+        Ok(())
+    }
+
+    /// Removes the effect at `index`.
+    pub fn remove(&mut self, index: usize) -> Result<()> {
+        self.check(index)?;
+        self.send(ChainCommand::Remove(index))?;
+        self.effects.remove(index);
+        Ok(())
+    }
+
+    /// Moves the effect at `from` so that it ends up at position `to`.
+    pub fn move_effect(&mut self, from: usize, to: usize) -> Result<()> {
+        self.check(from)?;
+        self.check(to)?;
+        self.send(ChainCommand::Move { from, to })?;
+        let effect = self.effects.remove(from);
+        self.effects.insert(to, effect);
+        Ok(())
+    }
+
+    /// Skips the effect at `index` while `bypassed` is set.
+    pub fn set_bypassed(&mut self, index: usize, bypassed: bool) -> Result<()> {
+        self.check(index)?;
+        self.send(ChainCommand::Bypass(index, bypassed))?;
+        self.effects[index].1 = bypassed;
+        Ok(())
+    }
+
+    /// Reorders every effect and sets its bypass state in a single edit:
+    /// entry `i` of `effects` names the current position of the effect that
+    /// moves to position `i` and whether it is bypassed there. Either the
+    /// whole arrangement is queued or, on error, nothing changes.
+    pub fn arrange(&mut self, effects: &[(usize, bool)]) -> Result<()> {
+        if effects.len() != self.effects.len() {
+            bail!(
+                "Arrangement lists {} effects for a chain of {}",
+                effects.len(),
+                self.effects.len()
+            );
+        }
+        let mut order = [0; MAX_EFFECTS];
+        let mut bypassed = [false; MAX_EFFECTS];
+        let mut seen = [false; MAX_EFFECTS];
+        for (position, &(index, bypass)) in effects.iter().enumerate() {
+            self.check(index)?;
+            if std::mem::replace(&mut seen[index], true) {
+                bail!("Effect {index} is listed twice");
+            }
+            order[position] = index as u8;
+            bypassed[position] = bypass;
+        }
+        self.send(ChainCommand::Arrange { order, bypassed })?;
+        self.effects = effects
+            .iter()
+            .map(|&(index, bypass)| (self.effects[index].0.clone(), bypass))
+            .collect();
+        Ok(())
+    }
+
+    fn check(&self, index: usize) -> Result<()> {
+        if index >= self.effects.len() {
+            bail!("No effect {} in a chain of {}", index, self.effects.len());
+        }
+        Ok(())
+    }
+
+    fn send(&self, command: ChainCommand) -> Result<()> {
+        // Free anything the chain has removed since the last edit.
+        while self.removed.try_recv().is_ok() {}
+        match self.commands.try_send(command) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => bail!("Effect chain is busy; try again"),
+            Err(TrySendError::Disconnected(_)) => bail!("Effect chain is no longer running"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Gain(f32);
+
+    impl AudioProcessor for Gain {
+        fn prepare(&mut self, _sample_rate: u32, _channels: usize) {}
+
+        fn process(&mut self, block: &mut [f32]) {
+            for sample in block {
+                *sample *= self.0;
+            }
+        }
+
+        fn reset(&mut self) {}
+    }
+
+    struct Offset(f32);
+
+    impl AudioProcessor for Offset {
+        fn prepare(&mut self, _sample_rate: u32, _channels: usize) {}
+
+        fn process(&mut self, block: &mut [f32]) {
+            for sample in block {
+                *sample += self.0;
+            }
+        }
+
+        fn reset(&mut self) {}
+
+        fn latency(&self) -> usize {
+            3
+        }
+    }
+
+    /// Outputs the channel count it was prepared with.
+    #[derive(Default)]
+    struct Channels(usize);
+
+    impl AudioProcessor for Channels {
+        fn prepare(&mut self, _sample_rate: u32, channels: usize) {
+            self.0 = channels;
+        }
+
+        fn process(&mut self, block: &mut [f32]) {
+            block.fill(self.0 as f32);
+        }
+
+        fn reset(&mut self) {}
+    }
+
+    fn run(chain: &mut EffectChain) -> f32 {
+        let mut block = [1.0];
+        chain.process(&mut block);
+        block[0]
+    }
+
+    #[test]
+    fn runs_effects_in_order_with_bypass() {
+        let mut chain = EffectChain::new();
+        chain.push("gain", Gain(2.0));
+        chain.push("offset", Offset(1.0));
+        assert_eq!(run(&mut chain), 3.0);
+        assert_eq!(chain.latency(), 3);
+
+        chain.move_effect(1, 0);
+        assert_eq!(chain.names().collect::<Vec<_>>(), ["offset", "gain"]);
+        assert_eq!(run(&mut chain), 4.0);
+
+        chain.set_bypassed(0, true);
+        assert_eq!(run(&mut chain), 2.0);
+        assert_eq!(chain.latency(), 0);
+    }
+
+    #[test]
+    fn remote_edits_apply_at_next_block() {
+        let mut chain = EffectChain::new();
+        chain.push("gain", Gain(2.0));
+        let mut remote = chain.remote();
+
+        remote.push("offset", Offset(1.0)).unwrap();
+        remote.move_effect(1, 0).unwrap();
+        assert_eq!(remote.names().collect::<Vec<_>>(), ["offset", "gain"]);
+        assert_eq!(run(&mut chain), 4.0);
+
+        remote.set_bypassed(1, true).unwrap();
+        assert_eq!(run(&mut chain), 2.0);
+
+        remote.arrange(&[(1, false), (0, true)]).unwrap();
+        assert_eq!(remote.names().collect::<Vec<_>>(), ["gain", "offset"]);
+        assert_eq!(run(&mut chain), 2.0);
+        assert_eq!(chain.names().collect::<Vec<_>>(), ["gain", "offset"]);
+        assert!(remote.arrange(&[(0, false), (0, false)]).is_err());
+        remote.arrange(&[(1, false), (0, true)]).unwrap();
+
+        remote.remove(0).unwrap();
+        assert!(remote.remove(1).is_err());
+        assert_eq!(run(&mut chain), 1.0);
+        assert_eq!(chain.len(), 1);
+
+        for _ in 1..MAX_EFFECTS {
+            remote.push("gain", Gain(1.0)).unwrap();
+        }
+        assert!(remote.push("gain", Gain(1.0)).is_err());
+        run(&mut chain);
+        assert_eq!(chain.len(), MAX_EFFECTS);
+    }
+
+    #[test]
+    fn prepares_effects_queued_before_the_chain() {
+        let mut chain = EffectChain::new();
+        let mut remote = chain.remote();
+        remote.push("early", Channels::default()).unwrap();
+        chain.prepare(48_000, 1);
+        assert_eq!(run(&mut chain), 1.0);
+
+        remote.push("late", Channels::default()).unwrap();
+        chain.prepare(48_000, 2);
+        assert_eq!(chain.len(), 2);
+        let mut block = [0.0; 2];
+        chain.process(&mut block);
+        assert_eq!(block, [2.0; 2]);
+    }
+}
@@ -0,0 +1,204 @@
+use crate::processor::{AudioProcessor, MAX_BLOCK_FRAMES};
+
+/// Gains routing each input channel to each output channel of interleaved audio.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelMatrix {
+    inputs: usize,
+    outputs: usize,
+    // Row-major: gains[output * inputs + input].
+    gains: Vec<f32>,
+}
+
+impl ChannelMatrix {
+    /// Creates a matrix from row-major gains, one row of `inputs` gains per output.
+    pub fn new(inputs: usize, outputs: usize, gains: Vec<f32>) -> Self {
+        assert!(inputs > 0 && outputs > 0, "channel counts must be non-zero");
+        assert_eq!(gains.len(), inputs * outputs, "gain count mismatch");
+        Self {
+            inputs,
+            outputs,
+            gains,
+        }
+    }
+
+    /// Default routing between two channel counts.
+    ///
+    /// Matching counts pass straight through, mono is copied to every output,
+    /// anything mixed down to mono is averaged, and otherwise output `n`
+    /// takes input `n % inputs`.
+    pub fn default_for(inputs: usize, outputs: usize) -> Self {
+        let mut gains = vec![0.0; inputs * outputs];
+        for output in 0..outputs {
+            let row = &mut gains[output * inputs..(output + 1) * inputs];
+            if outputs == 1 {
+                row.fill(1.0 / inputs as f32);
+            } else {
+                row[output % inputs] = 1.0;
+            }
+        }
+        Self::new(inputs, outputs, gains)
+    }
+
+    pub fn inputs(&self) -> usize {
+        self.inputs
+    }
+
+    pub fn outputs(&self) -> usize {
+        self.outputs
+    }
+
+    pub fn gain(&self, output: usize, input: usize) -> f32 {
+        self.gains[output * self.inputs + input]
+    }
+
+    pub fn set_gain(&mut self, output: usize, input: usize, gain: f32) {
+        self.gains[output * self.inputs + input] = gain;
+    }
+
+    /// Mixes interleaved `input` frames into `output`, which must hold the
+    /// same number of frames at the output channel count.
+    pub fn apply(&self, input: &[f32], output: &mut [f32]) {
+        assert_eq!(
+            input.len() / self.inputs,
+            output.len() / self.outputs,
+            "frame count mismatch"
+        );
+        let frames = input
+            .chunks_exact(self.inputs)
+            .zip(output.chunks_exact_mut(self.outputs));
+
+        for (frame_in, frame_out) in frames {
+            for (sample, row) in frame_out
+                .iter_mut()
+                .zip(self.gains.chunks_exact(self.inputs))
+            {
+                *sample = row.iter().zip(frame_in).map(|(g, s)| g * s).sum();
+            }
+        }
+    }
+}
+
+/// Runs an independent processor on every channel of interleaved audio.
+///
+/// Processors are created by the factory when the channel count is known,
+/// in [`AudioProcessor::prepare`], and each is prepared as a mono processor.
+pub struct PerChannel<P> {
+    make_processor: Box<dyn FnMut() -> P + Send>,
+    processors: Vec<P>,
+    scratch: Vec<f32>,
+}
+
+impl<P: AudioProcessor> PerChannel<P> {
+    /// Creates a wrapper that builds one processor per channel with `make_processor`.
+    pub fn new(make_processor: impl FnMut() -> P + Send + 'static) -> Self {
+        Self {
+            make_processor: Box::new(make_processor),
+            processors: Vec::new(),
+            scratch: Vec::new(),
+        }
+    }
+
+    pub fn channels(&self) -> usize {
+        self.processors.len()
+    }
+
+    pub fn processors(&self) -> &[P] {
+        &self.processors
+    }
+
+    /// De-interleaves `data`, processes each channel, and re-interleaves in place.
+    pub fn process_interleaved(&mut self, data: &mut [f32]) {
+        let channels = self.processors.len();
+        if channels == 0 {
+            return;
+        }
+        self.scratch.resize(data.len() / channels, 0.0);
+
+        for (channel, processor) in self.processors.iter_mut().enumerate() {
+            for (sample, frame) in self.scratch.iter_mut().zip(data.chunks_exact(channels)) {
+                *sample = frame[channel];
+            }
+            processor.process(&mut self.scratch);
+            for (sample, frame) in self.scratch.iter().zip(data.chunks_exact_mut(channels)) {
+                frame[channel] = *sample;
+            }
+        }
+    }
+}
+
+impl<P: AudioProcessor> AudioProcessor for PerChannel<P> {
+    fn prepare(&mut self, sample_rate: u32, channels: usize) {
+        assert!(channels > 0, "need at least one channel");
+        self.processors.truncate(channels);
+        while self.processors.len() < channels {
+            self.processors.push((self.make_processor)());
+        }
+        for processor in &mut self.processors {
+            processor.prepare(sample_rate, 1);
+        }
+        self.scratch.reserve(MAX_BLOCK_FRAMES);
+    }
+
+    fn process(&mut self, block: &mut [f32]) {
+        self.process_interleaved(block);
+    }
+
+    fn reset(&mut self) {
+        for processor in &mut self.processors {
+            processor.reset();
+        }
+    }
+
+    fn latency(&self) -> usize {
+        self.processors
+            .iter()
+            .map(|processor| processor.latency())
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Gain(f32);
+
+    impl AudioProcessor for Gain {
+        fn prepare(&mut self, _sample_rate: u32, _channels: usize) {}
+
+        fn process(&mut self, block: &mut [f32]) {
+            for sample in block {
+                *sample *= self.0;
+            }
+        }
+
+        fn reset(&mut self) {}
+    }
+
+    #[test]
+    fn default_matrix_routes_common_layouts() {
+        let mut out = [0.0; 4];
+        ChannelMatrix::default_for(1, 2).apply(&[0.5, -0.5], &mut out);
+        assert_eq!(out, [0.5, 0.5, -0.5, -0.5]);
+
+        let mut out = [0.0; 2];
+        ChannelMatrix::default_for(2, 1).apply(&[1.0, 0.0, 0.5, 0.5], &mut out);
+        assert_eq!(out, [0.5, 0.5]);
+
+        let mut out = [0.0; 4];
+        ChannelMatrix::default_for(2, 2).apply(&[1.0, 2.0, 3.0, 4.0], &mut out);
+        assert_eq!(out, [1.0, 2.0, 3.0, 4.0]);
+    }
+
+    #[test]
+    fn processes_each_channel_independently() {
+        let mut gains = [1.0, -1.0].into_iter();
+        let mut per_channel = PerChannel::new(move || Gain(gains.next().unwrap()));
+        per_channel.prepare(48_000, 2);
+        let mut data = [1.0, 1.0, 2.0, 2.0, 3.0, 3.0];
+
+        per_channel.process_interleaved(&mut data);
+        assert_eq!(data, [1.0, -1.0, 2.0, -2.0, 3.0, -3.0]);
+    }
+}
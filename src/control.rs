@@ -0,0 +1,207 @@
+use crate::autotune::AutoTuneSettings;
+use crate::chain::ChainRemote;
+use crate::harmonizer::HarmonizerSettings;
+use crate::params::{Param, ParamRegistry};
+use crate::pitch_shifter::{ratio_to_semitones, semitones_to_ratio};
+use anyhow::{Context, Result, bail};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+/// A pitch change typed at the interactive prompt.
+///
+/// Intervals are semitones by default or cents with a `c` suffix:
+///
+/// - `+7`, `-12`, `+3.5c`: set the shift relative to the original pitch
+/// - `x1.5`: set the pitch factor directly
+/// - `++1`, `--50c`: nudge the current shift up or down
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PitchCommand {
+    /// Set the shift to this many semitones.
+    Set(f32),
+    /// Set the pitch factor.
+    Ratio(f32),
+    /// Move the current shift by this many semitones.
+    Nudge(f32),
+}
+
+impl FromStr for PitchCommand {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        if let Some(ratio) = s.strip_prefix('x') {
+            let ratio: f32 = ratio
+                .trim()
+                .parse()
+                .with_context(|| format!("Invalid ratio: {ratio}"))?;
+            if !(ratio.is_finite() && ratio > 0.0) {
+                bail!("Ratio must be positive");
+            }
+            return Ok(Self::Ratio(ratio));
+        }
+        if let Some(amount) = s.strip_prefix("++") {
+            return Ok(Self::Nudge(parse_interval(amount)?));
+        }
+        if let Some(amount) = s.strip_prefix("--") {
+            return Ok(Self::Nudge(-parse_interval(amount)?));
+        }
+        if s.starts_with(['+', '-']) || s == "0" {
+            return Ok(Self::Set(parse_interval(s)?));
+        }
+        bail!("Unrecognised pitch command: {s}")
+    }
+}
+
+/// Parses `7`, `-12`, `3.5c` into semitones.
+pub(crate) fn parse_interval(s: &str) -> Result<f32> {
+    let s = s.trim();
+    let (number, scale) = match s.strip_suffix('c') {
+        Some(cents) => (cents, 0.01),
+        None => (s.strip_suffix("st").unwrap_or(s), 1.0),
+    };
+    let value: f32 = number
+        .trim()
+        .parse()
+        .with_context(|| format!("Invalid interval: {s}"))?;
+    if !value.is_finite() {
+        bail!("Invalid interval: {s}");
+    }
+    Ok(value * scale)
+}
+
+/// Allowed pitch shift, in semitones relative to the original pitch.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PitchRange {
+    pub min_semitones: f32,
+    pub max_semitones: f32,
+}
+
+impl PitchRange {
+    /// A range of `semitones` either side of the original pitch.
+    pub fn symmetric(semitones: f32) -> Self {
+        Self {
+            min_semitones: -semitones,
+            max_semitones: semitones,
+        }
+    }
+
+    pub fn contains(&self, semitones: f32) -> bool {
+        (self.min_semitones..=self.max_semitones).contains(&semitones)
+    }
+}
+
+impl Default for PitchRange {
+    fn default() -> Self {
+        Self::symmetric(24.0)
+    }
+}
+
+/// Validates pitch commands and writes them to the shared pitch factor.
+///
+/// Clones write to the same pitch factor, so the prompt and MIDI input can
+/// each hold one.
+#[derive(Clone)]
+pub struct PitchControl {
+    pitch_factor: Param,
+    range: PitchRange,
+}
+
+impl PitchControl {
+    pub fn new(pitch_factor: Param, range: PitchRange) -> Self {
+        Self {
+            pitch_factor,
+            range,
+        }
+    }
+
+    pub fn range(&self) -> PitchRange {
+        self.range
+    }
+
+    pub fn pitch_factor(&self) -> f32 {
+        self.pitch_factor.get()
+    }
+
+    /// Current shift in semitones.
+    pub fn semitones(&self) -> f32 {
+        ratio_to_semitones(self.pitch_factor())
+    }
+
+    /// Applies `command` and returns the new shift in semitones.
+    ///
+    /// Shifts outside the configured range are rejected and leave the
+    /// pitch unchanged.
+    pub fn apply(&self, command: PitchCommand) -> Result<f32> {
+        let semitones = match command {
+            PitchCommand::Set(semitones) => semitones,
+            PitchCommand::Ratio(ratio) => ratio_to_semitones(ratio),
+            PitchCommand::Nudge(delta) => self.semitones() + delta,
+        };
+
+        if !self.range.contains(semitones) {
+            bail!(
+                "{semitones:+.2} st is outside the allowed range {:+} to {:+} st",
+                self.range.min_semitones,
+                self.range.max_semitones
+            );
+        }
+        self.pitch_factor.set(match command {
+            PitchCommand::Ratio(ratio) => ratio,
+            _ => semitones_to_ratio(semitones),
+        });
+        Ok(semitones)
+    }
+}
+
+/// Everything the control thread can change while the chain is running.
+pub struct Controls {
+    pub params: ParamRegistry,
+    pub autotune: Arc<Mutex<AutoTuneSettings>>,
+    pub harmony: Arc<Mutex<HarmonizerSettings>>,
+    pub effects: ChainRemote,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pitch_shifter::PITCH_FACTOR;
+
+    fn parse(s: &str) -> PitchCommand {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_intervals_ratios_and_nudges() {
+        assert_eq!(parse("+7"), PitchCommand::Set(7.0));
+        assert_eq!(parse("-12"), PitchCommand::Set(-12.0));
+        assert_eq!(parse("0"), PitchCommand::Set(0.0));
+        assert_eq!(parse("+3.5c"), PitchCommand::Set(0.035));
+        assert_eq!(parse("-2st"), PitchCommand::Set(-2.0));
+        assert_eq!(parse("x1.5"), PitchCommand::Ratio(1.5));
+        assert_eq!(parse("++1"), PitchCommand::Nudge(1.0));
+        assert_eq!(parse("--50c"), PitchCommand::Nudge(-0.5));
+
+        for bad in ["7", "+", "+abc", "x0", "x-1", "++", "+infc"] {
+            assert!(bad.parse::<PitchCommand>().is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn applies_commands_within_range() {
+        let pitch_factor = Param::new(PITCH_FACTOR);
+        let control = PitchControl::new(pitch_factor.clone(), PitchRange::symmetric(12.0));
+
+        control.apply(PitchCommand::Set(12.0)).unwrap();
+        assert!((pitch_factor.get() - 2.0).abs() < 1e-6);
+
+        assert!(control.apply(PitchCommand::Nudge(0.5)).is_err());
+        assert!((control.semitones() - 12.0).abs() < 1e-4);
+
+        control.apply(PitchCommand::Nudge(-0.5)).unwrap();
+        assert!((control.semitones() - 11.5).abs() < 1e-4);
+
+        control.apply(PitchCommand::Ratio(0.5)).unwrap();
+        assert_eq!(control.pitch_factor(), 0.5);
+        assert!(control.apply(PitchCommand::Ratio(0.4)).is_err());
+    }
+}
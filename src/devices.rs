@@ -0,0 +1,257 @@
+use crate::error::AudioError;
+use anyhow::{Result, anyhow};
+use cpal::traits::{DeviceTrait, HostTrait};
+use std::io::Write;
+use std::str::FromStr;
+
+/// Which device to open, as given on the command line.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum DeviceSelector {
+    /// The host's default device.
+    #[default]
+    Default,
+    /// Position in the host's device list, as printed by [`write_device_list`].
+    Index(usize),
+    /// Exact device name, or failing that a unique case-insensitive substring.
+    Name(String),
+}
+
+impl FromStr for DeviceSelector {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Err(anyhow!("Device name must not be empty"));
+        }
+        Ok(match s.parse() {
+            Ok(index) => Self::Index(index),
+            Err(_) if s.eq_ignore_ascii_case("default") => Self::Default,
+            Err(_) => Self::Name(s.to_string()),
+        })
+    }
+}
+
+impl DeviceSelector {
+    /// Picks the matching entry from a list of device names.
+    pub(crate) fn find(&self, names: &[String]) -> Result<usize> {
+        match self {
+            Self::Default => Err(anyhow!("No default device to look up by name")),
+            Self::Index(index) if *index < names.len() => Ok(*index),
+            Self::Index(index) => Err(anyhow!(
+                "Device index {index} out of range ({} devices)",
+                names.len()
+            )),
+            Self::Name(name) => {
+                if let Some(index) = names.iter().position(|n| n == name) {
+                    return Ok(index);
+                }
+                let needle = name.to_lowercase();
+                let matches: Vec<usize> = (0..names.len())
+                    .filter(|&i| names[i].to_lowercase().contains(&needle))
+                    .collect();
+                match matches[..] {
+                    [index] => Ok(index),
+                    [] => Err(anyhow!("No device matches \"{name}\"")),
+                    _ => Err(anyhow!(
+                        "\"{name}\" matches several devices: {}",
+                        matches
+                            .iter()
+                            .map(|&i| names[i].as_str())
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    )),
+                }
+            }
+        }
+    }
+}
+
+/// Opens the audio host called `name` (e.g. `alsa`, `jack`), or the default host.
+pub fn host_by_name(name: Option<&str>) -> Result<cpal::Host, AudioError> {
+    let Some(name) = name else {
+        return Ok(cpal::default_host());
+    };
+
+    let id = cpal::available_hosts()
+        .into_iter()
+        .find(|id| id.name().eq_ignore_ascii_case(name))
+        .ok_or_else(|| {
+            let available: Vec<&str> = cpal::available_hosts().iter().map(|id| id.name()).collect();
+            AudioError::DeviceNotFound(format!(
+                "Unknown host \"{name}\" (available: {})",
+                available.join(", ")
+            ))
+        })?;
+    Ok(cpal::host_from_id(id)?)
+}
+
+/// Finds the capture device chosen by `selector`.
+pub fn input_device(
+    host: &cpal::Host,
+    selector: &DeviceSelector,
+) -> Result<cpal::Device, AudioError> {
+    if *selector == DeviceSelector::Default {
+        return host
+            .default_input_device()
+            .ok_or_else(|| AudioError::DeviceNotFound("No input device available".into()));
+    }
+    let mut devices: Vec<cpal::Device> = host.input_devices()?.collect();
+    let index = selector
+        .find(&device_names(&devices))
+        .map_err(|err| AudioError::DeviceNotFound(format!("Input device: {err}")))?;
+    Ok(devices.swap_remove(index))
+}
+
+/// Finds the playback device chosen by `selector`.
+pub fn output_device(
+    host: &cpal::Host,
+    selector: &DeviceSelector,
+) -> Result<cpal::Device, AudioError> {
+    if *selector == DeviceSelector::Default {
+        return host
+            .default_output_device()
+            .ok_or_else(|| AudioError::DeviceNotFound("No output device available".into()));
+    }
+    let mut devices: Vec<cpal::Device> = host.output_devices()?.collect();
+    let index = selector
+        .find(&device_names(&devices))
+        .map_err(|err| AudioError::DeviceNotFound(format!("Output device: {err}")))?;
+    Ok(devices.swap_remove(index))
+}
+
+fn device_names(devices: &[cpal::Device]) -> Vec<String> {
+    devices
+        .iter()
+        .map(|device| device.name().unwrap_or_default())
+        .collect()
+}
+
+/// Prints every available host with its input and output devices and the
+/// stream configs each device supports.
+pub fn write_device_list(out: &mut impl Write) -> Result<()> {
+    let default_host = cpal::default_host().id();
+
+    for id in cpal::available_hosts() {
+        let marker = if id == default_host { " (default)" } else { "" };
+        writeln!(out, "Host: {}{}", id.name(), marker)?;
+
+        let host = match cpal::host_from_id(id) {
+            Ok(host) => host,
+            Err(err) => {
+                writeln!(out, "  unavailable: {}", err)?;
+                continue;
+            }
+        };
+
+        let default_input = host.default_input_device().and_then(|d| d.name().ok());
+        writeln!(out, "  Input devices:")?;
+        for (index, device) in host.input_devices()?.enumerate() {
+            let configs = device
+                .supported_input_configs()
+                .map(|configs| configs.collect::<Vec<_>>());
+            write_device(out, index, &device, default_input.as_deref(), configs)?;
+        }
+
+        let default_output = host.default_output_device().and_then(|d| d.name().ok());
+        writeln!(out, "  Output devices:")?;
+        for (index, device) in host.output_devices()?.enumerate() {
+            let configs = device
+                .supported_output_configs()
+                .map(|configs| configs.collect::<Vec<_>>());
+            write_device(out, index, &device, default_output.as_deref(), configs)?;
+        }
+    }
+    Ok(())
+}
+
+fn write_device(
+    out: &mut impl Write,
+    index: usize,
+    device: &cpal::Device,
+    default_name: Option<&str>,
+    configs: Result<Vec<cpal::SupportedStreamConfigRange>, cpal::SupportedStreamConfigsError>,
+) -> Result<()> {
+    let name = device.name().unwrap_or_else(|_| "<unknown>".to_string());
+    let marker = if default_name == Some(name.as_str()) {
+        " (default)"
+    } else {
+        ""
+    };
+    writeln!(out, "    [{}] {}{}", index, name, marker)?;
+
+    match configs {
+        Ok(configs) => {
+            for config in configs {
+                let buffer = match config.buffer_size() {
+                    cpal::SupportedBufferSize::Range { min, max } => {
+                        format!("buffer {min}-{max} frames")
+                    }
+                    cpal::SupportedBufferSize::Unknown => "buffer size unknown".to_string(),
+                };
+                writeln!(
+                    out,
+                    "        {} ch, {}-{} Hz, {}, {}",
+                    config.channels(),
+                    config.min_sample_rate().0,
+                    config.max_sample_rate().0,
+                    config.sample_format(),
+                    buffer
+                )?;
+            }
+        }
+        Err(err) => writeln!(out, "        configs unavailable: {}", err)?,
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names() -> Vec<String> {
+        [
+            "default",
+            "USB Audio CODEC",
+            "HDA Intel PCH: ALC892 Analog",
+            "USB Mic",
+        ]
+        .map(String::from)
+        .to_vec()
+    }
+
+    #[test]
+    fn parses_indices_names_and_default() {
+        assert_eq!(
+            "2".parse::<DeviceSelector>().unwrap(),
+            DeviceSelector::Index(2)
+        );
+        assert_eq!(
+            "USB Mic".parse::<DeviceSelector>().unwrap(),
+            DeviceSelector::Name("USB Mic".into())
+        );
+        assert_eq!(
+            "Default".parse::<DeviceSelector>().unwrap(),
+            DeviceSelector::Default
+        );
+        assert!(" ".parse::<DeviceSelector>().is_err());
+    }
+
+    #[test]
+    fn finds_devices_by_index_exact_name_or_unique_substring() {
+        let names = names();
+        assert_eq!(DeviceSelector::Index(1).find(&names).unwrap(), 1);
+        assert!(DeviceSelector::Index(4).find(&names).is_err());
+
+        assert_eq!(
+            DeviceSelector::Name("default".into()).find(&names).unwrap(),
+            0
+        );
+        assert_eq!(
+            DeviceSelector::Name("alc892".into()).find(&names).unwrap(),
+            2
+        );
+        assert!(DeviceSelector::Name("usb".into()).find(&names).is_err());
+        assert!(DeviceSelector::Name("jack".into()).find(&names).is_err());
+    }
+}
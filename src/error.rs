@@ -0,0 +1,115 @@
+use std::fmt;
+
+/// Errors from opening and running audio devices.
+#[derive(Debug)]
+pub enum AudioError {
+    /// The requested host, or a device matching the selector, doesn't exist.
+    DeviceNotFound(String),
+    /// The device can't be opened with a format, rate, buffer size or
+    /// channel layout this crate can use.
+    UnsupportedConfig(String),
+    /// The host refused to create a stream.
+    BuildStream(cpal::BuildStreamError),
+    /// A stream was created but failed to start.
+    PlayStream(cpal::PlayStreamError),
+    /// The device went away, e.g. it was unplugged, and could not be
+    /// reconnected.
+    DeviceDisconnected,
+}
+
+impl fmt::Display for AudioError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DeviceNotFound(message) | Self::UnsupportedConfig(message) => {
+                f.write_str(message)
+            }
+            Self::BuildStream(err) => write!(f, "Could not open stream: {err}"),
+            Self::PlayStream(err) => write!(f, "Could not start stream: {err}"),
+            Self::DeviceDisconnected => f.write_str("Audio device disconnected"),
+        }
+    }
+}
+
+impl std::error::Error for AudioError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::BuildStream(err) => Some(err),
+            Self::PlayStream(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<cpal::BuildStreamError> for AudioError {
+    fn from(err: cpal::BuildStreamError) -> Self {
+        match err {
+            cpal::BuildStreamError::DeviceNotAvailable => Self::DeviceDisconnected,
+            cpal::BuildStreamError::StreamConfigNotSupported => {
+                Self::UnsupportedConfig(err.to_string())
+            }
+            err => Self::BuildStream(err),
+        }
+    }
+}
+
+impl From<cpal::PlayStreamError> for AudioError {
+    fn from(err: cpal::PlayStreamError) -> Self {
+        match err {
+            cpal::PlayStreamError::DeviceNotAvailable => Self::DeviceDisconnected,
+            err => Self::PlayStream(err),
+        }
+    }
+}
+
+impl From<cpal::DefaultStreamConfigError> for AudioError {
+    fn from(err: cpal::DefaultStreamConfigError) -> Self {
+        match err {
+            cpal::DefaultStreamConfigError::DeviceNotAvailable => Self::DeviceDisconnected,
+            err => Self::UnsupportedConfig(err.to_string()),
+        }
+    }
+}
+
+impl From<cpal::SupportedStreamConfigsError> for AudioError {
+    fn from(err: cpal::SupportedStreamConfigsError) -> Self {
+        match err {
+            cpal::SupportedStreamConfigsError::DeviceNotAvailable => Self::DeviceDisconnected,
+            err => Self::UnsupportedConfig(err.to_string()),
+        }
+    }
+}
+
+impl From<cpal::DevicesError> for AudioError {
+    fn from(err: cpal::DevicesError) -> Self {
+        Self::DeviceNotFound(format!("Could not list devices: {err}"))
+    }
+}
+
+impl From<cpal::HostUnavailable> for AudioError {
+    fn from(err: cpal::HostUnavailable) -> Self {
+        Self::DeviceNotFound(err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lost_devices_map_to_disconnected() {
+        assert!(matches!(
+            AudioError::from(cpal::BuildStreamError::DeviceNotAvailable),
+            AudioError::DeviceDisconnected
+        ));
+        assert!(matches!(
+            AudioError::from(cpal::PlayStreamError::DeviceNotAvailable),
+            AudioError::DeviceDisconnected
+        ));
+        assert!(matches!(
+            AudioError::from(cpal::BuildStreamError::StreamConfigNotSupported),
+            AudioError::UnsupportedConfig(_)
+        ));
+        let err = AudioError::DeviceNotFound("No input device available".into());
+        assert_eq!(err.to_string(), "No input device available");
+    }
+}
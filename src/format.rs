@@ -0,0 +1,176 @@
+use crate::error::AudioError;
+use cpal::{SampleFormat, SupportedStreamConfig, SupportedStreamConfigRange};
+
+/// Sample formats the streams convert, in order of preference when the
+/// device default can't be used. 64-bit integers aren't among them; no
+/// sound hardware uses them.
+const PREFERRED_FORMATS: [SampleFormat; 8] = [
+    SampleFormat::F32,
+    SampleFormat::I32,
+    SampleFormat::I16,
+    SampleFormat::F64,
+    SampleFormat::U32,
+    SampleFormat::U16,
+    SampleFormat::I8,
+    SampleFormat::U8,
+];
+
+/// Whether streams of `format` can be converted to and from the internal f32 format.
+pub fn is_supported(format: SampleFormat) -> bool {
+    PREFERRED_FORMATS.contains(&format)
+}
+
+/// Picks the config to open a device with: its default if the format is
+/// usable, otherwise the most preferred supported format at the default
+/// rate and channel count.
+pub fn choose_config(
+    default: SupportedStreamConfig,
+    supported: &[SupportedStreamConfigRange],
+) -> Result<SupportedStreamConfig, AudioError> {
+    if is_supported(default.sample_format()) {
+        return Ok(default);
+    }
+
+    let rate = default.sample_rate();
+    for format in PREFERRED_FORMATS {
+        let range = supported.iter().find(|range| {
+            range.sample_format() == format
+                && range.channels() == default.channels()
+                && (range.min_sample_rate()..=range.max_sample_rate()).contains(&rate)
+        });
+        if let Some(range) = range {
+            return Ok(range.with_sample_rate(rate));
+        }
+    }
+    Err(AudioError::UnsupportedConfig(format!(
+        "No supported sample format (device default is {})",
+        default.sample_format()
+    )))
+}
+
+/// Triangular (TPDF) dither added before quantising to a low bit depth.
+///
+/// Only 8- and 16-bit integer formats are dithered; wider formats and
+/// floats pass through unchanged.
+#[derive(Debug, Clone)]
+pub struct Dither {
+    // Size of one quantisation step in f32 units, or 0 when disabled.
+    lsb: f32,
+    state: u32,
+}
+
+impl Dither {
+    pub fn new(format: SampleFormat, enabled: bool) -> Self {
+        let bits = match format {
+            SampleFormat::I8 | SampleFormat::U8 => 8,
+            SampleFormat::I16 | SampleFormat::U16 => 16,
+            _ => 0,
+        };
+        let lsb = if enabled && bits > 0 {
+            1.0 / (1u32 << (bits - 1)) as f32
+        } else {
+            0.0
+        };
+        Self {
+            lsb,
+            state: 0x9E37_79B9,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.lsb > 0.0
+    }
+
+    /// Adds up to one LSB of triangular noise to `sample`.
+    pub fn apply(&mut self, sample: f32) -> f32 {
+        if self.lsb == 0.0 {
+            return sample;
+        }
+        let noise = self.next_uniform() + self.next_uniform() - 1.0;
+        sample + noise * self.lsb
+    }
+
+    /// Uniform value in [0, 1) from a xorshift generator.
+    fn next_uniform(&mut self) -> f32 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        (self.state >> 8) as f32 / (1u32 << 24) as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cpal::{SampleRate, SupportedBufferSize};
+
+    #[test]
+    fn keeps_usable_device_default() {
+        let default = SupportedStreamConfig::new(
+            2,
+            SampleRate(48_000),
+            SupportedBufferSize::Unknown,
+            SampleFormat::I16,
+        );
+        let supported = [SupportedStreamConfigRange::new(
+            2,
+            SampleRate(8_000),
+            SampleRate(96_000),
+            SupportedBufferSize::Unknown,
+            SampleFormat::F32,
+        )];
+        assert_eq!(choose_config(default.clone(), &supported).unwrap(), default);
+    }
+
+    #[test]
+    fn falls_back_from_unusable_default() {
+        let default = SupportedStreamConfig::new(
+            2,
+            SampleRate(44_100),
+            SupportedBufferSize::Unknown,
+            SampleFormat::I64,
+        );
+        let range = |channels, format| {
+            SupportedStreamConfigRange::new(
+                channels,
+                SampleRate(8_000),
+                SampleRate(96_000),
+                SupportedBufferSize::Unknown,
+                format,
+            )
+        };
+        // F32 is preferred but only in mono, so I16 is picked.
+        let supported = [
+            range(2, SampleFormat::I64),
+            range(1, SampleFormat::F32),
+            range(2, SampleFormat::I16),
+        ];
+        let config = choose_config(default.clone(), &supported).unwrap();
+        assert_eq!(config.sample_format(), SampleFormat::I16);
+        assert_eq!(config.channels(), 2);
+        assert_eq!(config.sample_rate(), SampleRate(44_100));
+
+        assert!(choose_config(default, &supported[..2]).is_err());
+    }
+
+    #[test]
+    fn dither_stays_within_one_lsb_and_averages_out() {
+        let mut dither = Dither::new(SampleFormat::I16, true);
+        let lsb = 1.0 / 32_768.0;
+        let samples: Vec<f32> = (0..100_000).map(|_| dither.apply(0.25)).collect();
+
+        assert!(samples.iter().all(|s| (s - 0.25).abs() <= lsb));
+        let mean = samples.iter().sum::<f32>() / samples.len() as f32;
+        assert!((mean - 0.25).abs() < lsb * 0.05);
+        assert!(samples.iter().any(|&s| s != 0.25));
+    }
+
+    #[test]
+    fn dither_only_applies_to_narrow_integer_formats() {
+        assert!(Dither::new(SampleFormat::U8, true).is_enabled());
+        assert!(!Dither::new(SampleFormat::I16, false).is_enabled());
+        assert!(!Dither::new(SampleFormat::F32, true).is_enabled());
+        assert!(!Dither::new(SampleFormat::I32, true).is_enabled());
+        assert_eq!(Dither::new(SampleFormat::F32, true).apply(0.5), 0.5);
+    }
+}
@@ -0,0 +1,516 @@
+use crate::control::parse_interval;
+use crate::params::Param;
+use crate::pitch_detection::{Note, PitchTracker};
+use crate::pitch_shifter::{DEFAULT_GLIDE, Engine, PITCH_FACTOR, PitchShifter, semitones_to_ratio};
+use crate::processor::{AudioProcessor, try_read};
+use crate::psola::PsolaShifter;
+use crate::scale::Scale;
+use crate::smoothing::SmoothedParam;
+use crate::wsola::WsolaShifter;
+use anyhow::{Context, Result, bail};
+use std::f32::consts::FRAC_PI_4;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Number of harmony voices a [`Harmonizer`] can run at once.
+pub const MAX_VOICES: usize = 4;
+/// Longest delay a voice can have.
+pub const MAX_DELAY: Duration = Duration::from_millis(500);
+/// Frames processed at a time, so scratch buffers can be sized up front.
+const CHUNK_FRAMES: usize = 256;
+/// Rate assumed until [`AudioProcessor::prepare`] is called.
+const DEFAULT_SAMPLE_RATE: u32 = 48_000;
+
+/// Distance of a harmony voice from the input.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Interval {
+    /// A fixed shift in semitones.
+    Semitones(f32),
+    /// This many steps up or down the harmonizer's scale from the note
+    /// being sung, so e.g. a third is major or minor as the key requires.
+    Diatonic(i32),
+}
+
+impl FromStr for Interval {
+    type Err = anyhow::Error;
+
+    /// Parses `+4`, `-12` or `+350c` as semitones and `+2d` as scale steps.
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        if let Some(steps) = s.strip_suffix('d') {
+            let steps = steps
+                .parse()
+                .with_context(|| format!("Invalid scale steps: {s}"))?;
+            return Ok(Self::Diatonic(steps));
+        }
+        Ok(Self::Semitones(parse_interval(s)?))
+    }
+}
+
+impl fmt::Display for Interval {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Semitones(semitones) => write!(f, "{semitones:+} st"),
+            Self::Diatonic(steps) => write!(f, "{steps:+} steps"),
+        }
+    }
+}
+
+/// One harmony voice.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Voice {
+    pub interval: Interval,
+    /// Linear gain.
+    pub level: f32,
+    /// Stereo position from -1 (left) to 1 (right).
+    pub pan: f32,
+    /// Delay after the dry signal, up to [`MAX_DELAY`].
+    pub delay: Duration,
+}
+
+impl Voice {
+    pub fn new(interval: Interval) -> Self {
+        Self {
+            interval,
+            level: 0.7,
+            pan: 0.0,
+            delay: Duration::ZERO,
+        }
+    }
+}
+
+impl FromStr for Voice {
+    type Err = anyhow::Error;
+
+    /// Parses `<interval> [level] [pan] [delay ms]`, e.g. `+2d 0.5 -0.7 30`.
+    fn from_str(s: &str) -> Result<Self> {
+        let mut words = s.split_whitespace();
+        let Some(interval) = words.next() else {
+            bail!("Missing interval");
+        };
+        let mut voice = Voice::new(interval.parse()?);
+        if let Some(level) = words.next() {
+            voice.level = level
+                .parse()
+                .with_context(|| format!("Invalid level: {level}"))?;
+            if !(voice.level.is_finite() && voice.level >= 0.0) {
+                bail!("Level must not be negative");
+            }
+        }
+        if let Some(pan) = words.next() {
+            voice.pan = pan.parse().with_context(|| format!("Invalid pan: {pan}"))?;
+            if !(-1.0..=1.0).contains(&voice.pan) {
+                bail!("Pan must be between -1 and 1");
+            }
+        }
+        if let Some(delay) = words.next() {
+            let ms: u64 = delay
+                .parse()
+                .with_context(|| format!("Invalid delay: {delay}"))?;
+            voice.delay = Duration::from_millis(ms);
+            if voice.delay > MAX_DELAY {
+                bail!("Delay must be at most {} ms", MAX_DELAY.as_millis());
+            }
+        }
+        if words.next().is_some() {
+            bail!("Expected <interval> [level] [pan] [delay ms]");
+        }
+        Ok(voice)
+    }
+}
+
+impl fmt::Display for Voice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}, level {:.2}, pan {:+.2}, delay {} ms",
+            self.interval,
+            self.level,
+            self.pan,
+            self.delay.as_millis()
+        )
+    }
+}
+
+/// Live harmonizer settings, shared with the control thread.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HarmonizerSettings {
+    pub voices: [Option<Voice>; MAX_VOICES],
+    /// Key that diatonic intervals are counted in.
+    pub scale: Scale,
+    /// Linear gain of the unshifted input.
+    pub dry_level: f32,
+}
+
+impl Default for HarmonizerSettings {
+    fn default() -> Self {
+        Self {
+            voices: [None; MAX_VOICES],
+            scale: Scale::major(0),
+            dry_level: 1.0,
+        }
+    }
+}
+
+impl HarmonizerSettings {
+    pub fn has_voices(&self) -> bool {
+        self.voices.iter().any(Option::is_some)
+    }
+
+    /// Sets the dry level, rejecting negative and non-finite values.
+    pub fn set_dry_level(&mut self, level: f32) -> Result<()> {
+        if !(level.is_finite() && level >= 0.0) {
+            bail!("Dry level must not be negative");
+        }
+        self.dry_level = level;
+        Ok(())
+    }
+}
+
+struct VoiceState {
+    pitch_factor: Param,
+    shifter: Box<dyn AudioProcessor>,
+    // Shifted output, indexed by absolute frame like the dry history.
+    delay_line: Vec<f32>,
+    active: bool,
+    // Diatonic shift in semitones, kept while the input is unvoiced.
+    semitones: f32,
+    // Settings last seen for this voice, kept while it fades out.
+    voice: Voice,
+    level: SmoothedParam,
+    pan: SmoothedParam,
+}
+
+/// Mixes pitch-shifted copies of the input with the dry signal.
+///
+/// Each of up to [`MAX_VOICES`] voices has its own pitch shifter, level,
+/// pan and delay. Voices are shifted from a mono mix of the input and
+/// panned across the first two channels; the dry signal keeps its channel
+/// layout and is delayed to stay in time with the voices. Diatonic voices
+/// follow the pitch of the input, tracked by the harmonizer itself.
+pub struct Harmonizer {
+    engine: Engine,
+    settings: Arc<Mutex<HarmonizerSettings>>,
+    // Last settings read from the control thread.
+    current: HarmonizerSettings,
+    tracker: PitchTracker,
+    voices: Vec<VoiceState>,
+    sample_rate: u32,
+    channels: usize,
+    // Interleaved dry input, indexed by absolute sample position.
+    dry: Vec<f32>,
+    dry_mask: usize,
+    voice_mask: usize,
+    written: usize,
+    mono: Vec<f32>,
+    shifted: Vec<f32>,
+}
+
+impl Harmonizer {
+    /// Creates a harmonizer whose voices use `engine` to shift pitch.
+    pub fn new(engine: Engine) -> Self {
+        let voices = (0..MAX_VOICES)
+            .map(|_| {
+                let pitch_factor = Param::new(PITCH_FACTOR);
+                VoiceState {
+                    shifter: voice_shifter(engine, pitch_factor.clone()),
+                    pitch_factor,
+                    delay_line: Vec::new(),
+                    active: false,
+                    semitones: 0.0,
+                    voice: Voice::new(Interval::Semitones(0.0)),
+                    level: SmoothedParam::new(0.0, DEFAULT_GLIDE, DEFAULT_SAMPLE_RATE),
+                    pan: SmoothedParam::new(0.0, DEFAULT_GLIDE, DEFAULT_SAMPLE_RATE),
+                }
+            })
+            .collect();
+        let mut harmonizer = Self {
+            engine,
+            settings: Arc::default(),
+            current: HarmonizerSettings::default(),
+            tracker: PitchTracker::new(),
+            voices,
+            sample_rate: 0,
+            channels: 1,
+            dry: Vec::new(),
+            dry_mask: 0,
+            voice_mask: 0,
+            written: 0,
+            mono: vec![0.0; CHUNK_FRAMES],
+            shifted: vec![0.0; CHUNK_FRAMES],
+        };
+        harmonizer.prepare(DEFAULT_SAMPLE_RATE, 1);
+        harmonizer
+    }
+
+    /// Starts from `settings` instead of no voices.
+    pub fn with_settings(self, settings: HarmonizerSettings) -> Self {
+        *self.settings.lock().unwrap() = settings;
+        self
+    }
+
+    /// Handle for changing the settings while audio is running.
+    pub fn settings(&self) -> Arc<Mutex<HarmonizerSettings>> {
+        Arc::clone(&self.settings)
+    }
+
+    pub fn engine(&self) -> Engine {
+        self.engine
+    }
+
+    /// Shift for `interval` given the note currently sung.
+    fn semitones(interval: Interval, scale: &Scale, sung: Option<Note>, held: f32) -> f32 {
+        match interval {
+            Interval::Semitones(semitones) => semitones,
+            Interval::Diatonic(steps) => sung
+                .and_then(|note| Some((note, scale.step(note, steps)?)))
+                .map_or(held, |(note, target)| {
+                    (target.0 - scale.nearest(note.0 as f32).unwrap_or(note).0) as f32
+                }),
+        }
+    }
+
+    fn process_chunk(&mut self, chunk: &mut [f32], settings: &HarmonizerSettings) {
+        let channels = self.channels;
+        let frames = chunk.len() / channels;
+        let latency = self.latency();
+        let sung = self.tracker.pitch().map(|pitch| pitch.note());
+
+        for (frame, samples) in chunk.chunks_exact(channels).enumerate() {
+            self.mono[frame] = samples.iter().sum::<f32>() / channels as f32;
+            for (channel, &sample) in samples.iter().enumerate() {
+                self.dry[((self.written + frame) * channels + channel) & self.dry_mask] = sample;
+            }
+        }
+
+        // Dry signal, delayed to line up with the shifters' output.
+        for (frame, samples) in chunk.chunks_exact_mut(channels).enumerate() {
+            let position = (self.written + frame).checked_sub(latency);
+            for (channel, sample) in samples.iter_mut().enumerate() {
+                *sample = position.map_or(0.0, |position| {
+                    self.dry[(position * channels + channel) & self.dry_mask]
+                }) * settings.dry_level;
+            }
+        }
+
+        for (state, voice) in self.voices.iter_mut().zip(&settings.voices) {
+            // Removed voices fade out before they stop.
+            let (voice, level) = match voice {
+                Some(voice) => (*voice, voice.level),
+                None if state.active && state.level.value() > 0.0 => (state.voice, 0.0),
+                None => {
+                    state.active = false;
+                    continue;
+                }
+            };
+            if !state.active {
+                state.shifter.reset();
+                state.delay_line.fill(0.0);
+                state.level.reset(0.0);
+                state.pan.reset(voice.pan.clamp(-1.0, 1.0));
+                state.active = true;
+            }
+            state.voice = voice;
+            if level != state.level.target() {
+                state.level.set_target(level);
+            }
+            let pan = voice.pan.clamp(-1.0, 1.0);
+            if pan != state.pan.target() {
+                state.pan.set_target(pan);
+            }
+            state.semitones =
+                Self::semitones(voice.interval, &settings.scale, sung, state.semitones);
+            state.pitch_factor.set(semitones_to_ratio(state.semitones));
+
+            let shifted = &mut self.shifted[..frames];
+            shifted.copy_from_slice(&self.mono[..frames]);
+            state.shifter.process(shifted);
+            for (frame, &sample) in shifted.iter().enumerate() {
+                state.delay_line[(self.written + frame) & self.voice_mask] = sample;
+            }
+
+            let delay =
+                (voice.delay.min(MAX_DELAY).as_secs_f32() * self.sample_rate as f32) as usize;
+            for (frame, samples) in chunk.chunks_exact_mut(channels).enumerate() {
+                let level = state.level.next_value();
+                let pan = state.pan.next_value();
+                let Some(position) = (self.written + frame).checked_sub(delay) else {
+                    continue;
+                };
+                let sample = state.delay_line[position & self.voice_mask] * level;
+                match samples {
+                    [mono] => *mono += sample,
+                    [left, right, ..] => {
+                        // Equal-power pan across the first two channels.
+                        let angle = (pan + 1.0) * FRAC_PI_4;
+                        *left += sample * angle.cos();
+                        *right += sample * angle.sin();
+                    }
+                    [] => {}
+                }
+            }
+        }
+        self.written += frames;
+    }
+}
+
+/// Mono shifter for one voice.
+fn voice_shifter(engine: Engine, pitch_factor: Param) -> Box<dyn AudioProcessor> {
+    match engine {
+        Engine::Vocoder => Box::new(PitchShifter::new(pitch_factor)),
+        Engine::Psola => Box::new(PsolaShifter::new(pitch_factor)),
+        Engine::Wsola => Box::new(WsolaShifter::new(pitch_factor)),
+    }
+}
+
+impl AudioProcessor for Harmonizer {
+    fn prepare(&mut self, sample_rate: u32, channels: usize) {
+        self.sample_rate = sample_rate;
+        self.channels = channels;
+        self.tracker.prepare(sample_rate, channels);
+        for voice in &mut self.voices {
+            voice.shifter.prepare(sample_rate, 1);
+            voice.level.set_sample_rate(sample_rate);
+            voice.pan.set_sample_rate(sample_rate);
+        }
+
+        let max_delay = (MAX_DELAY.as_secs_f32() * sample_rate as f32).ceil() as usize;
+        let voice_len = (max_delay + CHUNK_FRAMES).next_power_of_two();
+        for voice in &mut self.voices {
+            voice.delay_line = vec![0.0; voice_len];
+        }
+        self.voice_mask = voice_len - 1;
+        let dry_len = ((self.latency() + CHUNK_FRAMES) * channels).next_power_of_two();
+        self.dry = vec![0.0; dry_len];
+        self.dry_mask = dry_len - 1;
+        self.reset();
+    }
+
+    fn process(&mut self, block: &mut [f32]) {
+        self.tracker.process(block);
+        if let Some(settings) = try_read(&self.settings) {
+            self.current = settings;
+        }
+        let settings = self.current;
+        for chunk in block.chunks_mut(CHUNK_FRAMES * self.channels) {
+            self.process_chunk(chunk, &settings);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.tracker.reset();
+        for voice in &mut self.voices {
+            voice.shifter.reset();
+            voice.delay_line.fill(0.0);
+            voice.active = false;
+            voice.level.reset(0.0);
+        }
+        self.dry.fill(0.0);
+        self.written = 0;
+    }
+
+    fn latency(&self) -> usize {
+        self.voices[0].shifter.latency()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pitch_detection::PitchDetector;
+    use std::f32::consts::PI;
+
+    fn voice(freq: f32, len: usize) -> Vec<f32> {
+        (0..len)
+            .map(|i| {
+                let phase = 2.0 * PI * freq * i as f32 / 48_000.0;
+                0.4 * phase.sin() + 0.2 * (2.0 * phase).sin() + 0.1 * (3.0 * phase).sin()
+            })
+            .collect()
+    }
+
+    fn fundamental(samples: &[f32]) -> f32 {
+        let mut detector = PitchDetector::new(48_000, 60.0, 1000.0);
+        detector.detect(samples).unwrap().frequency
+    }
+
+    #[test]
+    fn parses_voices() {
+        let voice: Voice = "+2d 0.5 -1 30".parse().unwrap();
+        assert_eq!(voice.interval, Interval::Diatonic(2));
+        assert_eq!(voice.level, 0.5);
+        assert_eq!(voice.pan, -1.0);
+        assert_eq!(voice.delay, Duration::from_millis(30));
+        assert_eq!(
+            "-5".parse::<Voice>().unwrap().interval,
+            Interval::Semitones(-5.0)
+        );
+        assert!("+4 0.5 2".parse::<Voice>().is_err());
+        assert!("+4 0.5 0 900".parse::<Voice>().is_err());
+    }
+
+    #[test]
+    fn ramps_voice_level_and_pan() {
+        let mut settings = HarmonizerSettings::default();
+        settings.voices[0] = Some(Voice {
+            pan: -1.0,
+            ..Voice::new(Interval::Semitones(4.0))
+        });
+        let mut harmonizer = Harmonizer::new(Engine::Psola).with_settings(settings);
+        harmonizer.prepare(48_000, 2);
+        let mut block = vec![0.1; 2 * 24_000];
+        harmonizer.process(&mut block);
+        let state = &harmonizer.voices[0];
+        assert!((state.level.value() - 0.7).abs() < 1e-4);
+        assert_eq!(state.pan.value(), -1.0);
+
+        // A change glides over a few milliseconds rather than jumping.
+        settings.voices[0] = Some(Voice {
+            level: 0.1,
+            pan: 1.0,
+            ..Voice::new(Interval::Semitones(4.0))
+        });
+        *harmonizer.settings().lock().unwrap() = settings;
+        harmonizer.process(&mut block[..2 * 48]);
+        let state = &harmonizer.voices[0];
+        assert!(state.level.value() > 0.1 && state.level.value() < 0.7);
+        assert!(state.pan.value() > -1.0 && state.pan.value() < 1.0);
+
+        // A removed voice fades out before it stops.
+        settings.voices[0] = None;
+        *harmonizer.settings().lock().unwrap() = settings;
+        harmonizer.process(&mut block[..2 * 48]);
+        assert!(harmonizer.voices[0].active);
+        harmonizer.process(&mut block);
+        assert!(!harmonizer.voices[0].active);
+    }
+
+    #[test]
+    fn pans_diatonic_voice_beside_the_dry_signal() {
+        // A3 in C major: a diatonic third up is C4, three semitones higher.
+        let mut settings = HarmonizerSettings {
+            dry_level: 0.0,
+            ..HarmonizerSettings::default()
+        };
+        settings.voices[0] = Some(Voice {
+            pan: 1.0,
+            level: 1.0,
+            ..Voice::new(Interval::Diatonic(2))
+        });
+        let mut harmonizer = Harmonizer::new(Engine::Psola).with_settings(settings);
+        harmonizer.prepare(48_000, 2);
+
+        let mono = voice(220.0, 48_000);
+        let mut stereo: Vec<f32> = mono.iter().flat_map(|&s| [s, s]).collect();
+        for block in stereo.chunks_mut(960) {
+            harmonizer.process(block);
+        }
+        let left: Vec<f32> = stereo.iter().step_by(2).copied().collect();
+        let right: Vec<f32> = stereo.iter().skip(1).step_by(2).copied().collect();
+        assert!(left[24_000..].iter().all(|s| s.abs() < 1e-6));
+        let expected = 220.0 * semitones_to_ratio(3.0);
+        assert!((fundamental(&right[24_000..]) - expected).abs() < 3.0);
+    }
+}
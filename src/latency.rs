@@ -0,0 +1,123 @@
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+const NO_MEASUREMENT: i64 = i64::MIN;
+
+/// Measures how long audio takes from its capture timestamp to its
+/// playback timestamp while passing through the ring buffer.
+///
+/// The input callback records where each captured block lands in the ring
+/// and when it was captured; the output callback compares that with the
+/// playback time of the frames it reads.
+pub struct LatencyMonitor {
+    sample_rate: f64,
+    // Timestamps are stored as nanoseconds relative to this.
+    base: Instant,
+    // Seqlock guarding the (frame, capture_ns) pair; odd while being written.
+    seq: AtomicU64,
+    frame: AtomicU64,
+    capture_ns: AtomicI64,
+    latency_ns: AtomicI64,
+}
+
+impl LatencyMonitor {
+    /// `sample_rate` is the rate of the frames counted in the ring buffer.
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate: sample_rate as f64,
+            base: Instant::now(),
+            seq: AtomicU64::new(0),
+            frame: AtomicU64::new(0),
+            capture_ns: AtomicI64::new(0),
+            latency_ns: AtomicI64::new(NO_MEASUREMENT),
+        }
+    }
+
+    /// Records that ring frame `frame` was captured at `capture`.
+    pub fn record_capture(&self, frame: u64, capture: Instant) {
+        self.record_capture_at(frame, self.nanos(&capture));
+    }
+
+    /// Records that ring frame `frame` will be heard at `playback`.
+    pub fn record_playback(&self, frame: u64, playback: Instant) {
+        self.record_playback_at(frame, self.nanos(&playback));
+    }
+
+    /// Smoothed capture-to-playback latency, once both streams have reported.
+    pub fn latency(&self) -> Option<Duration> {
+        match self.latency_ns.load(Ordering::Relaxed) {
+            NO_MEASUREMENT => None,
+            nanos => Some(Duration::from_nanos(nanos.max(0) as u64)),
+        }
+    }
+
+    fn record_capture_at(&self, frame: u64, capture_ns: i64) {
+        let seq = self.seq.load(Ordering::Relaxed);
+        self.seq.store(seq.wrapping_add(1), Ordering::Relaxed);
+        std::sync::atomic::fence(Ordering::Release);
+        self.frame.store(frame, Ordering::Relaxed);
+        self.capture_ns.store(capture_ns, Ordering::Relaxed);
+        self.seq.store(seq.wrapping_add(2), Ordering::Release);
+    }
+
+    fn record_playback_at(&self, frame: u64, playback_ns: i64) {
+        // Skip this block rather than spin if the input side is mid-update.
+        let before = self.seq.load(Ordering::Acquire);
+        if before == 0 || before % 2 == 1 {
+            return;
+        }
+        let marker_frame = self.frame.load(Ordering::Relaxed);
+        let marker_ns = self.capture_ns.load(Ordering::Relaxed);
+        std::sync::atomic::fence(Ordering::Acquire);
+        if self.seq.load(Ordering::Relaxed) != before {
+            return;
+        }
+
+        let frames_after_marker = frame.wrapping_sub(marker_frame) as i64;
+        let capture_ns = marker_ns + (frames_after_marker as f64 * 1e9 / self.sample_rate) as i64;
+        let measured = playback_ns - capture_ns;
+
+        let previous = self.latency_ns.load(Ordering::Relaxed);
+        let smoothed = if previous == NO_MEASUREMENT {
+            measured
+        } else {
+            previous + (measured - previous) / 8
+        };
+        self.latency_ns.store(smoothed, Ordering::Relaxed);
+    }
+
+    fn nanos(&self, instant: &Instant) -> i64 {
+        match instant.checked_duration_since(self.base) {
+            Some(after) => after.as_nanos() as i64,
+            None => -(self.base.duration_since(*instant).as_nanos() as i64),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn measures_capture_to_playback_through_the_buffer() {
+        let monitor = LatencyMonitor::new(1000);
+        assert_eq!(monitor.latency(), None);
+
+        // Frame 100 captured at t = 1 s; frame 90 (captured 10 ms earlier)
+        // is played at t = 1.03 s.
+        monitor.record_capture_at(100, 1_000_000_000);
+        monitor.record_playback_at(90, 1_030_000_000);
+        assert_eq!(monitor.latency(), Some(Duration::from_millis(40)));
+
+        // Later measurements are smoothed rather than replacing the value.
+        monitor.record_playback_at(100, 1_048_000_000);
+        assert_eq!(monitor.latency(), Some(Duration::from_millis(41)));
+    }
+
+    #[test]
+    fn ignores_playback_before_any_capture() {
+        let monitor = LatencyMonitor::new(48_000);
+        monitor.record_playback_at(0, 5_000_000);
+        assert_eq!(monitor.latency(), None);
+    }
+}
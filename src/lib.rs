@@ -0,0 +1,61 @@
+//! Real-time audio effects for live microphone input.
+//!
+//! Effects implement [`AudioProcessor`] and process blocks of samples in
+//! place; an [`EffectChain`] runs several of them in order. [`StreamHost`]
+//! connects a processor between a capture and a playback device through a
+//! lock-free ring buffer, and [`offline`] runs the same processing over WAV
+//! files.
+
+pub mod autotune;
+pub mod chain;
+pub mod channels;
+pub mod control;
+pub mod devices;
+pub mod error;
+pub mod format;
+pub mod harmonizer;
+pub mod latency;
+pub mod meter;
+pub mod midi;
+pub mod mix;
+pub mod offline;
+pub mod params;
+pub mod pitch_detection;
+pub mod pitch_shifter;
+pub mod preset;
+pub mod processor;
+pub mod psola;
+pub mod resample;
+pub mod ring_buffer;
+pub mod scale;
+pub mod smoothing;
+pub mod stream;
+pub mod vocoder;
+pub mod wav;
+pub mod wsola;
+
+pub use autotune::{AutoTune, AutoTuneSettings};
+pub use chain::{ChainRemote, EffectChain};
+pub use channels::{ChannelMatrix, PerChannel};
+pub use devices::DeviceSelector;
+pub use error::AudioError;
+pub use harmonizer::{Harmonizer, HarmonizerSettings, Interval, Voice};
+pub use latency::LatencyMonitor;
+pub use meter::{LevelMeter, Metered, SharedLevels};
+pub use midi::{MidiConnection, MidiControl, MidiMapping};
+pub use mix::{DryWet, Limiter};
+pub use offline::ProcessOptions;
+pub use params::{Param, ParamInfo, ParamRegistry, Unit};
+pub use pitch_detection::{Note, Pitch, PitchDetector, PitchTracker, SharedPitch};
+pub use pitch_shifter::{Engine, PitchShifter, ratio_to_semitones, semitones_to_ratio};
+pub use preset::{Preset, PresetStore};
+pub use processor::AudioProcessor;
+pub use psola::PsolaShifter;
+pub use resample::Resampler;
+pub use ring_buffer::{DriftPolicy, RingConfig, RingStats};
+pub use scale::Scale;
+pub use smoothing::{Ramp, SmoothedParam};
+pub use stream::{ReconnectPolicy, RunningStreams, StreamHost, StreamStatus};
+pub use vocoder::{Formants, PhaseVocoder};
+pub use wav::AudioBuffer;
+pub use wsola::WsolaShifter;
@@ -1,81 +1,424 @@
-use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use std::sync::{Arc, Mutex};
+use anyhow::Context;
+use audio_effects::autotune::MAX_CORRECTION;
+use audio_effects::control::{Controls, PitchCommand, PitchControl, PitchRange};
+use audio_effects::devices;
+use audio_effects::harmonizer::MAX_VOICES;
+use audio_effects::midi::{CcMapping, DEFAULT_BEND_RANGE};
+use audio_effects::mix::{LIMITER_NAME, MIX, OUTPUT_GAIN};
+use audio_effects::pitch_shifter::{FORMANT_FACTOR, PITCH_FACTOR};
+use audio_effects::scale::{self, Scale};
+use audio_effects::smoothing::Ramp;
+use audio_effects::{
+    AudioError, AudioProcessor, AutoTune, AutoTuneSettings, DeviceSelector, DryWet, EffectChain,
+    Engine, Harmonizer, HarmonizerSettings, Limiter, Metered, MidiConnection, MidiControl,
+    MidiMapping, Param, ParamRegistry, PerChannel, Pitch, PitchShifter, PitchTracker, Preset,
+    PresetStore, ProcessOptions, PsolaShifter, ReconnectPolicy, RingConfig, SharedPitch,
+    StreamHost, StreamStatus, Voice, WsolaShifter, offline, ratio_to_semitones, semitones_to_ratio,
+};
+use clap::{Parser, Subcommand, ValueEnum};
 use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::Duration;
 
-/// Naive pitch shifter using linear interpolation
-fn pitch_shift(samples: &[f32], pitch_factor: f32) -> Vec<f32> {
-    let input_len = samples.len() as f32;
-    let output_len = (input_len / pitch_factor) as usize;
-    let mut output = Vec::with_capacity(output_len);
+mod tui;
 
-    for i in 0..output_len {
-        let src_index = i as f32 * pitch_factor;
-        let idx = src_index.floor() as usize;
-        let frac = src_index.fract();
+/// Live pitch shifter; run without a subcommand to process the microphone.
+#[derive(Parser)]
+#[command(version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Print every audio host, device and supported config, then exit
+    #[arg(long)]
+    list_devices: bool,
+    /// Audio host to use, e.g. alsa or jack
+    #[arg(long)]
+    host: Option<String>,
+    /// Capture device, by name or by index from --list-devices
+    #[arg(long, default_value = "default")]
+    input: DeviceSelector,
+    /// Playback device, by name or by index from --list-devices
+    #[arg(long, default_value = "default")]
+    output: DeviceSelector,
+    /// Frames per device callback; must be within the devices' supported range
+    #[arg(long, conflicts_with = "latency_ms")]
+    buffer_frames: Option<u32>,
+    /// Device callback size in milliseconds, converted to frames at the output rate
+    #[arg(long)]
+    latency_ms: Option<f32>,
+    /// Dither playback to 8/16-bit integer devices
+    #[arg(long)]
+    dither: bool,
+    /// Exit instead of waiting for a disconnected device to come back
+    #[arg(long)]
+    no_reconnect: bool,
+    /// Pitch-shifting algorithm
+    #[arg(long, value_enum, default_value_t = Shifter::Vocoder)]
+    shifter: Shifter,
+    /// Keep formants in place so voices don't change timbre (vocoder only)
+    #[arg(long)]
+    preserve_formants: bool,
+    /// Largest pitch shift accepted at the prompt, in semitones either way
+    #[arg(long, default_value_t = 24.0)]
+    max_shift: f32,
+    /// Shape of the glide between pitch settings
+    #[arg(long, value_enum, default_value_t = Glide::Exponential)]
+    glide: Glide,
+    /// Glide time in milliseconds (time constant for exponential glides)
+    #[arg(long, default_value_t = 20)]
+    glide_ms: u64,
+    /// Auto-tune the input to a key or note list, e.g. "C major", "F# minor" or "C D E G A"
+    #[arg(long)]
+    autotune: Option<Scale>,
+    /// Auto-tune retune time in milliseconds, on top of the glide; 0 snaps instantly
+    #[arg(long, default_value_t = 10)]
+    retune_ms: u64,
+    /// Share of natural pitch variation auto-tune leaves in, from 0 to 1
+    #[arg(long, default_value_t = 0.0)]
+    humanize: f32,
+    /// Add a harmony voice, "<interval> [level] [pan] [delay ms]", e.g. "+4" or
+    /// "+2d 0.5 -1 30" (+2d = two steps up the harmony key); repeat for more
+    #[arg(long = "voice", value_name = "VOICE", allow_hyphen_values = true)]
+    voices: Vec<Voice>,
+    /// Key that diatonic harmony intervals are counted in
+    #[arg(long, default_value = "C major")]
+    harmony_key: Scale,
+    /// Share of pitch-shifted signal in the output, from 0 (dry) to 1 (shifted only)
+    #[arg(long, default_value_t = 1.0)]
+    mix: f32,
+    /// Output gain in dB, applied before the limiter
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+    gain_db: f32,
+    /// Start from a preset saved with the save command
+    #[arg(long)]
+    preset: Option<String>,
+    /// Show a full-screen interface with meters instead of the line prompt
+    #[arg(long)]
+    tui: bool,
+    /// Take control from a MIDI input port, by name or index; "default" creates
+    /// a virtual port for other programs to connect to
+    #[arg(long, value_name = "PORT")]
+    midi: Option<DeviceSelector>,
+    /// Only listen to this MIDI channel, from 1 to 16
+    #[arg(long, value_parser = clap::value_parser!(u8).range(1..=16))]
+    midi_channel: Option<u8>,
+    /// Pitch shift at full pitch-bend, in semitones either way
+    #[arg(long, default_value_t = DEFAULT_BEND_RANGE)]
+    bend_range: f32,
+    /// Map a MIDI controller to a parameter, e.g. "7=gain" or "1=mix"; repeat for more
+    #[arg(long = "midi-cc", value_name = "CC=PARAM")]
+    midi_controllers: Vec<CcMapping>,
+    /// Don't shift the input to notes played on the MIDI keyboard
+    #[arg(long)]
+    no_midi_notes: bool,
+}
 
-        let s1 = samples.get(idx).copied().unwrap_or(0.0);
-        let s2 = samples.get(idx + 1).copied().unwrap_or(0.0);
+#[derive(Clone, Copy, ValueEnum)]
+enum Shifter {
+    /// Phase vocoder: works on any material
+    Vocoder,
+    /// TD-PSOLA: lower latency, for a single voice
+    Psola,
+    /// WSOLA: overlap-added grains aligned by waveform similarity
+    Wsola,
+}
 
-        output.push(s1 + frac * (s2 - s1));
+impl From<Shifter> for Engine {
+    fn from(shifter: Shifter) -> Self {
+        match shifter {
+            Shifter::Vocoder => Engine::Vocoder,
+            Shifter::Psola => Engine::Psola,
+            Shifter::Wsola => Engine::Wsola,
+        }
     }
+}
 
-    output
+#[derive(Clone, Copy, ValueEnum)]
+enum Glide {
+    Off,
+    Linear,
+    Exponential,
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let host = cpal::default_host();
-
-    let input_device = host
-        .default_input_device()
-        .expect("No input device available");
-    let output_device = host
-        .default_output_device()
-        .expect("No output device available");
-
-    let input_config = input_device.default_input_config()?.config();
-    let output_config = output_device.default_output_config()?.config();
-
-    println!("  Input config: {:?}", input_config);
-    println!(" Output config: {:?}", output_config);
-
-    let buffer = Arc::new(Mutex::new(vec![0.0_f32; 1024]));
-    let pitch_factor = Arc::new(Mutex::new(1.0_f32)); // shared control
-
-    // Input stream
-    let buffer_in = Arc::clone(&buffer);
-    let input_stream = input_device.build_input_stream(
-        &input_config,
-        move |data: &[f32], _: &cpal::InputCallbackInfo| {
-            let mut buf = buffer_in.lock().unwrap();
-            for (i, sample) in data.iter().enumerate().take(buf.len()) {
-                buf[i] = *sample;
-            }
+impl Cli {
+    fn ramp(&self) -> Ramp {
+        let time = Duration::from_millis(self.glide_ms);
+        match self.glide {
+            Glide::Off => Ramp::None,
+            Glide::Linear => Ramp::Linear(time),
+            Glide::Exponential => Ramp::Exponential(time),
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Pitch-shift a WAV file (16/24-bit PCM or 32-bit float) without an audio device
+    Process {
+        input: PathBuf,
+        output: PathBuf,
+        /// Shift in semitones, e.g. -3 or 7
+        #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+        semitones: f32,
+        /// Pitch-shifting algorithm
+        #[arg(long, value_enum, default_value_t = Shifter::Vocoder)]
+        shifter: Shifter,
+        /// Change the duration without changing pitch, e.g. 1.5 for 50% longer
+        #[arg(long, default_value_t = 1.0)]
+        time_ratio: f32,
+        /// Keep formants in place so voices don't change timbre
+        #[arg(long)]
+        preserve_formants: bool,
+        /// Move the preserved formants by this many semitones
+        #[arg(
+            long,
+            default_value_t = 0.0,
+            allow_negative_numbers = true,
+            requires = "preserve_formants"
+        )]
+        formant_shift: f32,
+        /// Resample the result to this rate in Hz
+        #[arg(long)]
+        sample_rate: Option<u32>,
+    },
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match run(&cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!(" Error: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(cli: &Cli) -> Result<(), Box<dyn std::error::Error>> {
+    match &cli.command {
+        Some(Command::Process {
+            input,
+            output,
+            semitones,
+            shifter,
+            time_ratio,
+            preserve_formants,
+            formant_shift,
+            sample_rate,
+        }) => {
+            let options = ProcessOptions {
+                engine: (*shifter).into(),
+                pitch_factor: semitones_to_ratio(*semitones),
+                time_ratio: *time_ratio,
+                formant_factor: preserve_formants.then(|| semitones_to_ratio(*formant_shift)),
+                sample_rate: *sample_rate,
+            };
+            offline::process_file(input, output, &options)?;
+            println!(" Wrote {}", output.display());
+            Ok(())
+        }
+        None if cli.list_devices => {
+            devices::write_device_list(&mut io::stdout().lock())?;
+            Ok(())
+        }
+        None => run_live(cli),
+    }
+}
+
+fn run_live(cli: &Cli) -> Result<(), Box<dyn std::error::Error>> {
+    let host = devices::host_by_name(cli.host.as_deref())?;
+    let mut host = StreamHost::new(&host, &cli.input, &cli.output)?;
+    host.set_dither(cli.dither);
+    if cli.no_reconnect {
+        host.set_reconnect_policy(ReconnectPolicy::NEVER);
+    }
+
+    println!("  Input device: {}", host.input_name());
+    println!(" Output device: {}", host.output_name());
+    println!(
+        "  Input config: {:?} ({})",
+        host.input_config(),
+        host.input_format()
+    );
+    println!(
+        " Output config: {:?} ({})",
+        host.output_config(),
+        host.output_format()
+    );
+    let (input_rate, output_rate) = (
+        host.input_config().sample_rate.0,
+        host.output_config().sample_rate.0,
+    );
+    if input_rate != output_rate {
+        println!("  Resampling {} Hz -> {} Hz", input_rate, output_rate);
+    }
+
+    let buffer_frames = cli.buffer_frames.or_else(|| {
+        cli.latency_ms
+            .map(|ms| (ms / 1000.0 * output_rate as f32).round().max(1.0) as u32)
+    });
+    if let Some(frames) = buffer_frames {
+        host.set_buffer_frames(frames)?;
+    }
+    match host.buffer_frames() {
+        Some(frames) => println!(
+            "  Buffer size: {} frames ({:.1} ms)",
+            frames,
+            frames as f32 * 1000.0 / output_rate as f32
+        ),
+        None => println!("  Buffer size: device default"),
+    }
+
+    if !matches!(cli.shifter, Shifter::Vocoder) && cli.preserve_formants {
+        return Err("--preserve-formants needs the vocoder shifter".into());
+    }
+
+    if cli.voices.len() > MAX_VOICES {
+        return Err(format!("At most {MAX_VOICES} harmony voices are supported").into());
+    }
+    if !(0.0..=1.0).contains(&cli.mix) {
+        return Err("--mix must be between 0 and 1".into());
+    }
+    if !(0.0..=1.0).contains(&cli.humanize) {
+        return Err("--humanize must be between 0 and 1".into());
+    }
+    let max_shift = ratio_to_semitones(PITCH_FACTOR.max);
+    if !(0.0..=max_shift).contains(&cli.max_shift) {
+        return Err(format!("--max-shift must be between 0 and {max_shift}").into());
+    }
+    if !(cli.bend_range.is_finite() && cli.bend_range >= 0.0) {
+        return Err("--bend-range must not be negative".into());
+    }
+    if !(OUTPUT_GAIN.min..=OUTPUT_GAIN.max).contains(&cli.gain_db) {
+        return Err(format!(
+            "--gain-db must be between {} and {}",
+            OUTPUT_GAIN.min, OUTPUT_GAIN.max
+        )
+        .into());
+    }
+
+    let mut params = ParamRegistry::new();
+    let shift_range = (
+        semitones_to_ratio(-cli.max_shift),
+        semitones_to_ratio(cli.max_shift),
+    );
+    let pitch_factor = params.add(PITCH_FACTOR.with_range(shift_range.0, shift_range.1));
+    let formant_factor = params.add(FORMANT_FACTOR.with_range(shift_range.0, shift_range.1));
+    let mix = params.add(MIX.with_default(cli.mix));
+    let gain_db = params.add(OUTPUT_GAIN.with_default(cli.gain_db));
+    // Written by auto-tune from the pitch factor, read by the shifters. The
+    // WSOLA shifter's latency grows with this range.
+    let shifted = (cli.max_shift + MAX_CORRECTION).min(max_shift);
+    let shifted_factor = Param::new(
+        PITCH_FACTOR.with_range(semitones_to_ratio(-shifted), semitones_to_ratio(shifted)),
+    );
+    let mut chain = EffectChain::new();
+    // Tracks the captured input before anything changes its pitch.
+    let tracker = PitchTracker::new();
+    let input_pitch = tracker.shared();
+    chain.push("tuner", tracker);
+    let autotune = AutoTune::new(pitch_factor.clone(), shifted_factor.clone()).with_settings(
+        AutoTuneSettings {
+            enabled: cli.autotune.is_some(),
+            scale: cli.autotune.unwrap_or_default(),
+            retune_speed: Duration::from_millis(cli.retune_ms),
+            humanize: cli.humanize,
         },
-        err_fn,
-        None,
-    )?;
-
-    // Output stream
-    let buffer_out = Arc::clone(&buffer);
-    let pitch_shared = Arc::clone(&pitch_factor);
-    let output_stream = output_device.build_output_stream(
-        &output_config,
-        move |output: &mut [f32], _: &cpal::OutputCallbackInfo| {
-            let buf = buffer_out.lock().unwrap();
-            let factor = *pitch_shared.lock().unwrap();
-            let shifted = pitch_shift(&buf, factor);
-            for i in 0..output.len() {
-                output[i] = *shifted.get(i).unwrap_or(&0.0);
+    );
+    let autotune_settings = autotune.settings();
+    chain.push("autotune", autotune);
+    let shifter_factor = shifted_factor.clone();
+    let shifter_formants = cli.preserve_formants.then(|| formant_factor.clone());
+    let ramp = cli.ramp();
+    let shifter: Box<dyn AudioProcessor> = match cli.shifter {
+        Shifter::Vocoder => Box::new(PerChannel::new(move || {
+            let shifter = PitchShifter::new(shifter_factor.clone()).with_ramp(ramp);
+            match &shifter_formants {
+                Some(formant_factor) => shifter.with_formants(formant_factor.clone()),
+                None => shifter,
             }
-        },
-        err_fn,
-        None,
-    )?;
+        })),
+        Shifter::Psola => Box::new(PerChannel::new(move || {
+            PsolaShifter::new(shifter_factor.clone()).with_ramp(ramp)
+        })),
+        Shifter::Wsola => Box::new(PerChannel::new(move || {
+            WsolaShifter::new(shifter_factor.clone()).with_ramp(ramp)
+        })),
+    };
+    chain.push("pitch", DryWet::new(shifter, mix.clone()));
+    let mut harmony = HarmonizerSettings {
+        scale: cli.harmony_key,
+        ..HarmonizerSettings::default()
+    };
+    for (slot, voice) in harmony.voices.iter_mut().zip(&cli.voices) {
+        *slot = Some(*voice);
+    }
+    let harmonizer = Harmonizer::new(cli.shifter.into()).with_settings(harmony);
+    let harmony_settings = harmonizer.settings();
+    chain.push("harmony", harmonizer);
+    // Skip the harmonizer, and its latency, until it has voices.
+    chain.set_bypassed(chain.len() - 1, !harmony.has_voices());
+    // Last, so nothing outside [-1, 1] reaches the device.
+    chain.push(LIMITER_NAME, Limiter::new(gain_db.clone()));
+    let mut controls = Controls {
+        params,
+        autotune: autotune_settings,
+        harmony: harmony_settings,
+        effects: chain.remote(),
+    };
+    let presets = PresetStore::open_default()?;
+    if let Some(name) = &cli.preset {
+        presets.load(name)?.apply(&mut controls)?;
+        println!("  Preset: {}", name);
+    }
+    let metered = Metered::new(chain);
+    let (input_levels, output_levels) = (metered.input_levels(), metered.output_levels());
+    let streams = host.start(metered, host.ring_config(RingConfig::default().drift))?;
 
-    input_stream.play()?;
-    output_stream.play()?;
+    let control = PitchControl::new(pitch_factor, PitchRange::symmetric(cli.max_shift));
+    let formants = PitchControl::new(formant_factor, PitchRange::symmetric(cli.max_shift));
 
-    println!("  Enter:\n  1 = Low pitch\n  2 = High pitch\n  0 = Normal pitch\n  q = Quit");
+    // Kept open until the session ends.
+    let _midi = match &cli.midi {
+        Some(port) => {
+            let mapping = MidiMapping {
+                channel: cli.midi_channel.map(|channel| channel - 1),
+                bend_range: cli.bend_range,
+                controllers: cli.midi_controllers.clone(),
+                notes: !cli.no_midi_notes,
+            };
+            let midi = MidiControl::new(
+                control.clone(),
+                &controls.params,
+                Arc::clone(&input_pitch),
+                &mapping,
+            )?;
+            let connection = MidiConnection::open(port, midi)?;
+            println!("  MIDI input: {}", connection.port_name());
+            Some(connection)
+        }
+        None => None,
+    };
+
+    if cli.tui {
+        return tui::run(tui::Live {
+            streams: &streams,
+            control: &control,
+            controls: &mut controls,
+            shifted_factor,
+            input_pitch,
+            input_levels,
+            output_levels,
+        });
+    }
+
+    println!(
+        "  Enter:\n  +7, -12, +3.5c = Shift in semitones or cents\n  x1.5 = Set pitch ratio\n  ++1, --50c = Nudge pitch\n  1 = Low pitch\n  2 = High pitch\n  0 = Normal pitch\n  f +3, f x1.2 = Shift formants (with --preserve-formants)\n  a = Toggle auto-tune\n  a C major, a +F#, a -F# = Auto-tune key or single notes\n  a speed 20, a humanize 0.3 = Retune time (ms) and humanize\n  h = Show harmony voices\n  h 1 +4, h 2 -2d 0.5 -1 30, h 2 off = Set harmony voice (interval level pan delay)\n  h key A minor, h dry 0.8 = Harmony key and dry level\n  m 0.5 = Dry/wet mix (0 = dry, 1 = shifted)\n  g -6 = Output gain in dB\n  b = Bypass pitch shifter\n  save NAME, load NAME, list, delete NAME = Presets\n  t = Tuner (Enter to stop)\n  l = Show latency\n  q = Quit"
+    );
 
     loop {
         print!("> ");
@@ -83,35 +426,302 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         let mut input = String::new();
         io::stdin().read_line(&mut input)?;
 
+        match streams.status() {
+            StreamStatus::Running => {}
+            StreamStatus::Reconnecting { attempt } => {
+                println!(" Waiting for the audio device (attempt {})", attempt)
+            }
+            StreamStatus::Disconnected => return Err(AudioError::DeviceDisconnected.into()),
+        }
+
         match input.trim() {
-            "1" => {
-                let mut factor = pitch_factor.lock().unwrap();
-                *factor = 0.7;
-                println!(" Set to LOW pitch");
+            "1" => match control.apply(PitchCommand::Ratio(0.7)) {
+                Ok(_) => println!(" Set to LOW pitch"),
+                Err(err) => println!(" {}", err),
+            },
+            "2" => match control.apply(PitchCommand::Ratio(1.3)) {
+                Ok(_) => println!(" Set to HIGH pitch"),
+                Err(err) => println!(" {}", err),
+            },
+            "0" => match control.apply(PitchCommand::Set(0.0)) {
+                Ok(_) => println!(" Set to NORMAL pitch"),
+                Err(err) => println!(" {}", err),
+            },
+            "b" => match tui::toggle_bypass(&mut controls) {
+                Ok(true) => println!(" Pitch shifter bypassed"),
+                Ok(false) => println!(" Pitch shifter active"),
+                Err(err) => println!(" {}", err),
+            },
+            "t" => run_tuner(&input_pitch)?,
+            "l" => match streams.latency() {
+                Some(latency) => println!(
+                    " Round-trip latency: {:.1} ms (+{:.1} ms processing)",
+                    latency.as_secs_f32() * 1000.0,
+                    streams.processor_latency().as_secs_f32() * 1000.0
+                ),
+                None => println!(" Latency not measured yet"),
+            },
+            "q" => {
+                println!(
+                    " Exiting... ({} underruns, {} overruns)",
+                    streams.stats().underruns(),
+                    streams.stats().overruns()
+                );
+                break;
             }
-            "2" => {
-                let mut factor = pitch_factor.lock().unwrap();
-                *factor = 1.3;
-                println!(" Set to HIGH pitch");
+            command if PRESET_COMMANDS.contains(&command.split(' ').next().unwrap_or_default()) => {
+                match preset_command(&presets, &mut controls, command) {
+                    Ok(status) => println!(" {}", status),
+                    Err(err) => println!(
+                        " {}. Use save <name>, load <name>, list or delete <name>.",
+                        err
+                    ),
+                }
             }
-            "0" => {
-                let mut factor = pitch_factor.lock().unwrap();
-                *factor = 1.0;
-                println!(" Set to NORMAL pitch");
+            command if command.starts_with('a') => {
+                match autotune_command(&controls.autotune, command[1..].trim()) {
+                    Ok(status) => println!(" {}", status),
+                    Err(err) => println!(
+                        " {}. Use a, a C major, a +F#, a speed 20 or a humanize 0.3.",
+                        err
+                    ),
+                }
             }
-            "q" => {
-                println!(" Exiting...");
-                break;
+            command if command.starts_with('h') => {
+                match harmony_command(&controls.harmony, command[1..].trim()) {
+                    Ok((status, has_voices)) => {
+                        let effects = &mut controls.effects;
+                        let bypassed = effects
+                            .position("harmony")
+                            .context("No harmonizer in the effect chain")
+                            .and_then(|index| effects.set_bypassed(index, !has_voices));
+                        if let Err(err) = bypassed {
+                            println!(" {}", err);
+                        }
+                        println!("{}", status);
+                    }
+                    Err(err) => println!(
+                        " {}. Use h, h 1 +4 0.7 -0.5 20, h 1 -2d, h 1 off, h key C major or h dry 1.",
+                        err
+                    ),
+                }
+            }
+            command if command.starts_with('m') => match command[1..].trim().parse::<f32>() {
+                Ok(value) if (0.0..=1.0).contains(&value) => {
+                    mix.set(value);
+                    println!(" Mix: {:.0}% shifted", value * 100.0);
+                }
+                _ => println!(" Use m followed by a mix from 0 to 1, e.g. m 0.5."),
+            },
+            command if command.starts_with('g') => match command[1..].trim().parse::<f32>() {
+                Ok(value) if value.is_finite() => {
+                    println!(" Output gain: {:+.1} dB", gain_db.set(value));
+                }
+                _ => println!(" Use g followed by a gain in dB, e.g. g -6."),
+            },
+            command if command.starts_with('f') => {
+                let command = command[1..].trim();
+                if !cli.preserve_formants {
+                    println!(" Formants follow the pitch; start with --preserve-formants");
+                    continue;
+                }
+                match command.parse().and_then(|command| formants.apply(command)) {
+                    Ok(semitones) => println!(" Formants: {:+.2} st", semitones),
+                    Err(err) => println!(" {}. Use f +3, f -50c, f x1.2 or f 0.", err),
+                }
+            }
+            command => match command.parse().and_then(|command| control.apply(command)) {
+                Ok(semitones) => println!(
+                    " Pitch: {:+.2} st (ratio {:.3})",
+                    semitones,
+                    control.pitch_factor()
+                ),
+                Err(err) => println!(
+                    " {}. Use +7, -3.5c, x1.5, ++1, 1, 2, 0, a, h, m, g, f +3, b, t, l, save, load, or q.",
+                    err
+                ),
+            },
+        }
+    }
+
+    Ok(())
+}
+
+/// Prompt commands that manage presets.
+const PRESET_COMMANDS: [&str; 4] = ["save", "load", "list", "delete"];
+
+/// Runs a `save`, `load`, `list` or `delete` prompt command and describes
+/// the result.
+fn preset_command(
+    presets: &PresetStore,
+    controls: &mut Controls,
+    command: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let (word, name) = command
+        .split_once(' ')
+        .map_or((command, ""), |(word, name)| (word, name.trim()));
+    if word != "list" && name.is_empty() {
+        return Err("Missing preset name".into());
+    }
+    Ok(match word {
+        "save" => {
+            presets.save(name, &Preset::capture(controls))?;
+            format!("Saved preset {} to {}", name, presets.path().display())
+        }
+        "load" => {
+            presets.load(name)?.apply(controls)?;
+            format!("Loaded preset {}", name)
+        }
+        "delete" => {
+            presets.delete(name)?;
+            format!("Deleted preset {}", name)
+        }
+        _ => {
+            let names = presets.names()?;
+            if names.is_empty() {
+                "No saved presets".to_string()
+            } else {
+                format!("Presets: {}", names.join(", "))
+            }
+        }
+    })
+}
+
+/// Applies an `a ...` prompt command to the auto-tune settings and
+/// describes the result.
+fn autotune_command(
+    settings: &Mutex<AutoTuneSettings>,
+    command: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let mut settings = settings.lock().unwrap_or_else(PoisonError::into_inner);
+    let (word, value) = command
+        .split_once(' ')
+        .map_or((command, ""), |(word, value)| (word, value.trim()));
+    match word {
+        "" => settings.enabled = !settings.enabled,
+        "speed" => settings.retune_speed = Duration::from_millis(value.parse()?),
+        "humanize" => {
+            let humanize: f32 = value.parse()?;
+            if !(0.0..=1.0).contains(&humanize) {
+                return Err("Humanize must be between 0 and 1".into());
             }
-            _ => {
-                println!(" Unknown command. Use 1, 2, 0, or q.");
+            settings.humanize = humanize;
+        }
+        _ => {
+            if let Some(name) = command.strip_prefix('+') {
+                settings
+                    .scale
+                    .set_note(scale::parse_pitch_class(name)?, true);
+            } else if let Some(name) = command.strip_prefix('-') {
+                settings
+                    .scale
+                    .set_note(scale::parse_pitch_class(name)?, false);
+            } else {
+                settings.scale = command.parse()?;
             }
+            settings.enabled = true;
+        }
+    }
+
+    Ok(if settings.enabled {
+        format!(
+            "Auto-tune on: {}, retune {} ms, humanize {:.2}",
+            settings.scale,
+            settings.retune_speed.as_millis(),
+            settings.humanize
+        )
+    } else {
+        "Auto-tune off".to_string()
+    })
+}
+
+/// Applies an `h ...` prompt command to the harmonizer settings. Returns a
+/// description of the voices and whether any are active.
+fn harmony_command(
+    settings: &Mutex<HarmonizerSettings>,
+    command: &str,
+) -> Result<(String, bool), Box<dyn std::error::Error>> {
+    let mut settings = settings.lock().unwrap_or_else(PoisonError::into_inner);
+    let (word, value) = command
+        .split_once(' ')
+        .map_or((command, ""), |(word, value)| (word, value.trim()));
+    match word {
+        "" => {}
+        "key" => settings.scale = value.parse()?,
+        "dry" => settings.set_dry_level(value.parse()?)?,
+        number => {
+            let index = match number.parse::<usize>() {
+                Ok(number @ 1..=MAX_VOICES) => number - 1,
+                _ => return Err(format!("Voices are numbered 1 to {MAX_VOICES}").into()),
+            };
+            settings.voices[index] = match value {
+                "off" => None,
+                voice => Some(voice.parse()?),
+            };
+        }
+    }
+
+    let mut status = format!(
+        " Harmony in {}, dry level {:.2}",
+        settings.scale, settings.dry_level
+    );
+    for (number, voice) in settings.voices.iter().enumerate() {
+        if let Some(voice) = voice {
+            status += &format!("\n  {}: {}", number + 1, voice);
         }
     }
+    if !settings.has_voices() {
+        status += "\n  No voices";
+    }
+    Ok((status, settings.has_voices()))
+}
 
+/// Shows a live tuner line for the captured input until Enter is pressed.
+fn run_tuner(pitch: &Arc<SharedPitch>) -> io::Result<()> {
+    let stop = Arc::new(AtomicBool::new(false));
+    let display = {
+        let stop = Arc::clone(&stop);
+        let pitch = Arc::clone(pitch);
+        thread::spawn(move || {
+            while !stop.load(Ordering::Relaxed) {
+                print!("\r{:<40}", tuner_line(pitch.get()));
+                let _ = io::stdout().flush();
+                thread::sleep(Duration::from_millis(50));
+            }
+            println!();
+        })
+    };
+
+    io::stdin().read_line(&mut String::new())?;
+    stop.store(true, Ordering::Relaxed);
+    let _ = display.join();
     Ok(())
 }
 
-fn err_fn(err: cpal::StreamError) {
-    eprintln!(" Stream error: {}", err);
+/// Note, cents meter and frequency, e.g. ` A4   [     |--   ] +20c   445.1 Hz`.
+fn tuner_line(pitch: Option<Pitch>) -> String {
+    let Some(pitch) = pitch else {
+        return " --   [     |     ]".to_string();
+    };
+    let cents = pitch.cents();
+    // One mark per 10 cents either side of the centre.
+    let marks = (cents / 10.0).round() as i32;
+    let meter: String = (-5..=5)
+        .map(|i| match i {
+            0 => '|',
+            i if (marks < 0 && (marks..0).contains(&i))
+                || (marks > 0 && (1..=marks).contains(&i)) =>
+            {
+                '-'
+            }
+            _ => ' ',
+        })
+        .collect();
+    format!(
+        " {:<4} [{}] {:+3.0}c {:7.1} Hz",
+        pitch.note().to_string(),
+        meter,
+        cents,
+        pitch.frequency
+    )
 }
@@ -0,0 +1,240 @@
+use crate::processor::AudioProcessor;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// Mono samples kept for waveform displays.
+pub const SCOPE_LEN: usize = 2048;
+/// How long each published level is measured over.
+const WINDOW: Duration = Duration::from_millis(20);
+/// Rate assumed until [`AudioProcessor::prepare`] is called.
+const DEFAULT_SAMPLE_RATE: u32 = 48_000;
+
+/// Latest levels and samples from a [`LevelMeter`], readable from any
+/// thread without locking.
+pub struct SharedLevels {
+    // f32 bits of the linear peak and RMS over the last window.
+    peak: AtomicU32,
+    rms: AtomicU32,
+    // Mono mix of the most recent samples, f32 bits, written in a circle.
+    scope: Box<[AtomicU32]>,
+    written: AtomicUsize,
+}
+
+impl SharedLevels {
+    fn new() -> Self {
+        Self {
+            peak: AtomicU32::new(0),
+            rms: AtomicU32::new(0),
+            scope: (0..SCOPE_LEN).map(|_| AtomicU32::new(0)).collect(),
+            written: AtomicUsize::new(0),
+        }
+    }
+
+    /// Largest absolute sample of the last window, where 1 is full scale.
+    pub fn peak(&self) -> f32 {
+        f32::from_bits(self.peak.load(Ordering::Relaxed))
+    }
+
+    pub fn rms(&self) -> f32 {
+        f32::from_bits(self.rms.load(Ordering::Relaxed))
+    }
+
+    /// Copies the most recent samples into `out`, oldest first. Samples
+    /// written while copying may show up early, which a display won't notice.
+    pub fn scope(&self, out: &mut [f32]) {
+        let len = out.len().min(SCOPE_LEN);
+        let end = self.written.load(Ordering::Acquire);
+        let start = end.wrapping_sub(len);
+        for (i, sample) in out[..len].iter_mut().enumerate() {
+            let slot = &self.scope[start.wrapping_add(i) % SCOPE_LEN];
+            *sample = f32::from_bits(slot.load(Ordering::Relaxed));
+        }
+    }
+}
+
+/// Pass-through effect that measures the level of the audio running
+/// through it and keeps its most recent samples for display.
+///
+/// Peak and RMS over all channels are published every 20 ms through
+/// [`LevelMeter::shared`].
+pub struct LevelMeter {
+    shared: Arc<SharedLevels>,
+    channels: usize,
+    window_frames: usize,
+    frames: usize,
+    peak: f32,
+    sum_squares: f64,
+}
+
+impl LevelMeter {
+    pub fn new() -> Self {
+        let mut meter = Self {
+            shared: Arc::new(SharedLevels::new()),
+            channels: 1,
+            window_frames: 0,
+            frames: 0,
+            peak: 0.0,
+            sum_squares: 0.0,
+        };
+        meter.prepare(DEFAULT_SAMPLE_RATE, 1);
+        meter
+    }
+
+    /// Handle for reading the levels from another thread.
+    pub fn shared(&self) -> Arc<SharedLevels> {
+        Arc::clone(&self.shared)
+    }
+
+    fn publish(&mut self) {
+        let samples = (self.frames * self.channels).max(1);
+        let rms = (self.sum_squares / samples as f64).sqrt() as f32;
+        self.shared
+            .peak
+            .store(self.peak.to_bits(), Ordering::Relaxed);
+        self.shared.rms.store(rms.to_bits(), Ordering::Relaxed);
+        self.frames = 0;
+        self.peak = 0.0;
+        self.sum_squares = 0.0;
+    }
+}
+
+impl Default for LevelMeter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AudioProcessor for LevelMeter {
+    fn prepare(&mut self, sample_rate: u32, channels: usize) {
+        self.channels = channels;
+        self.window_frames = ((WINDOW.as_secs_f32() * sample_rate as f32) as usize).max(1);
+        self.reset();
+    }
+
+    fn process(&mut self, block: &mut [f32]) {
+        let mut written = self.shared.written.load(Ordering::Relaxed);
+        for frame in block.chunks_exact(self.channels) {
+            let mut mono = 0.0;
+            for &sample in frame {
+                self.peak = self.peak.max(sample.abs());
+                self.sum_squares += (sample * sample) as f64;
+                mono += sample;
+            }
+            mono /= self.channels as f32;
+            self.shared.scope[written % SCOPE_LEN].store(mono.to_bits(), Ordering::Relaxed);
+            written = written.wrapping_add(1);
+
+            self.frames += 1;
+            if self.frames == self.window_frames {
+                self.publish();
+            }
+        }
+        self.shared.written.store(written, Ordering::Release);
+    }
+
+    fn reset(&mut self) {
+        self.frames = 0;
+        self.peak = 0.0;
+        self.sum_squares = 0.0;
+    }
+}
+
+/// Runs a processor between two [`LevelMeter`]s, measuring what goes into
+/// it and what comes out.
+pub struct Metered<P> {
+    input: LevelMeter,
+    processor: P,
+    output: LevelMeter,
+}
+
+impl<P: AudioProcessor> Metered<P> {
+    pub fn new(processor: P) -> Self {
+        Self {
+            input: LevelMeter::new(),
+            processor,
+            output: LevelMeter::new(),
+        }
+    }
+
+    pub fn input_levels(&self) -> Arc<SharedLevels> {
+        self.input.shared()
+    }
+
+    pub fn output_levels(&self) -> Arc<SharedLevels> {
+        self.output.shared()
+    }
+}
+
+impl<P: AudioProcessor> AudioProcessor for Metered<P> {
+    fn prepare(&mut self, sample_rate: u32, channels: usize) {
+        self.input.prepare(sample_rate, channels);
+        self.processor.prepare(sample_rate, channels);
+        self.output.prepare(sample_rate, channels);
+    }
+
+    fn process(&mut self, block: &mut [f32]) {
+        self.input.process(block);
+        self.processor.process(block);
+        self.output.process(block);
+    }
+
+    fn reset(&mut self) {
+        self.input.reset();
+        self.processor.reset();
+        self.output.reset();
+    }
+
+    fn latency(&self) -> usize {
+        self.processor.latency()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn measures_levels_and_keeps_recent_samples() {
+        let mut meter = LevelMeter::new();
+        meter.prepare(1000, 2);
+        let levels = meter.shared();
+
+        // A square wave at half scale, louder on the left.
+        let mut block: Vec<f32> = (0..100)
+            .flat_map(|i| {
+                let s = if i % 2 == 0 { 0.5 } else { -0.5 };
+                [s, s * 0.5]
+            })
+            .collect();
+        let input = block.clone();
+        meter.process(&mut block);
+        assert_eq!(block, input);
+        assert_eq!(levels.peak(), 0.5);
+        let rms = ((0.25 + 0.0625) / 2.0_f32).sqrt();
+        assert!((levels.rms() - rms).abs() < 1e-6);
+
+        let mut recent = [0.0; 3];
+        levels.scope(&mut recent);
+        assert_eq!(recent, [-0.375, 0.375, -0.375]);
+    }
+
+    #[test]
+    fn meters_both_sides_of_a_processor() {
+        struct Halve;
+        impl AudioProcessor for Halve {
+            fn prepare(&mut self, _: u32, _: usize) {}
+            fn process(&mut self, block: &mut [f32]) {
+                block.iter_mut().for_each(|s| *s *= 0.5);
+            }
+            fn reset(&mut self) {}
+        }
+
+        let mut metered = Metered::new(Halve);
+        metered.prepare(1000, 1);
+        let (input, output) = (metered.input_levels(), metered.output_levels());
+        metered.process(&mut [0.8; 20]);
+        assert_eq!(input.peak(), 0.8);
+        assert_eq!(output.peak(), 0.4);
+    }
+}
@@ -0,0 +1,431 @@
+use crate::control::{PitchCommand, PitchControl};
+use crate::devices::DeviceSelector;
+use crate::params::{Param, ParamInfo, ParamRegistry, Unit};
+use crate::pitch_detection::{Note, SharedPitch};
+use crate::pitch_shifter::{ratio_to_semitones, semitones_to_ratio};
+use anyhow::{Context, Result, anyhow};
+use midir::{MidiInput, MidiInputConnection};
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// Name the MIDI client and its ports are registered under.
+const CLIENT_NAME: &str = "audio_effects";
+/// Pitch-bend range unless [`MidiMapping::bend_range`] says otherwise.
+pub const DEFAULT_BEND_RANGE: f32 = 2.0;
+/// Largest pitch-bend value either side of the centre.
+const BEND_SCALE: f32 = 8192.0;
+
+/// A channel message from a MIDI controller. Channels count from 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MidiMessage {
+    NoteOn {
+        channel: u8,
+        note: u8,
+        velocity: u8,
+    },
+    NoteOff {
+        channel: u8,
+        note: u8,
+    },
+    ControlChange {
+        channel: u8,
+        controller: u8,
+        value: u8,
+    },
+    /// Bend from -8192 to 8191, 0 being the centre.
+    PitchBend {
+        channel: u8,
+        value: i16,
+    },
+}
+
+impl MidiMessage {
+    /// Decodes a raw message, or `None` for anything else, such as clock or
+    /// system exclusive messages.
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        let [status, data1, data2, ..] = *bytes else {
+            return None;
+        };
+        let channel = status & 0x0f;
+        let (data1, data2) = (data1 & 0x7f, data2 & 0x7f);
+        Some(match status & 0xf0 {
+            0x80 => Self::NoteOff {
+                channel,
+                note: data1,
+            },
+            // Note-on with zero velocity is the usual shorthand for note-off.
+            0x90 if data2 == 0 => Self::NoteOff {
+                channel,
+                note: data1,
+            },
+            0x90 => Self::NoteOn {
+                channel,
+                note: data1,
+                velocity: data2,
+            },
+            0xb0 => Self::ControlChange {
+                channel,
+                controller: data1,
+                value: data2,
+            },
+            0xe0 => Self::PitchBend {
+                channel,
+                value: ((data2 as i16) << 7 | data1 as i16) - 8192,
+            },
+            _ => return None,
+        })
+    }
+
+    pub fn channel(&self) -> u8 {
+        match *self {
+            Self::NoteOn { channel, .. }
+            | Self::NoteOff { channel, .. }
+            | Self::ControlChange { channel, .. }
+            | Self::PitchBend { channel, .. } => channel,
+        }
+    }
+}
+
+/// A controller number and the parameter it drives, written `7=gain`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CcMapping {
+    pub controller: u8,
+    /// Id of a parameter in the [`ParamRegistry`].
+    pub param: String,
+}
+
+impl FromStr for CcMapping {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (controller, param) = s
+            .split_once('=')
+            .ok_or_else(|| anyhow!("Expected CC=PARAMETER, e.g. 7=gain"))?;
+        let controller = controller
+            .trim()
+            .parse()
+            .ok()
+            .filter(|&controller: &u8| controller < 128)
+            .with_context(|| format!("Controller must be 0 to 127, not {controller}"))?;
+        Ok(Self {
+            controller,
+            param: param.trim().to_string(),
+        })
+    }
+}
+
+/// How incoming MIDI messages change the controls.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MidiMapping {
+    /// Only listen to this channel (0 to 15), or to all of them.
+    pub channel: Option<u8>,
+    /// Shift in semitones at full pitch-bend, either way.
+    pub bend_range: f32,
+    pub controllers: Vec<CcMapping>,
+    /// Whether note-on shifts the input to the played note.
+    pub notes: bool,
+}
+
+impl Default for MidiMapping {
+    fn default() -> Self {
+        Self {
+            channel: None,
+            bend_range: DEFAULT_BEND_RANGE,
+            controllers: Vec::new(),
+            notes: true,
+        }
+    }
+}
+
+/// Applies MIDI messages to the same pitch control and parameters the
+/// prompt uses.
+///
+/// - Pitch-bend moves the shift continuously by up to
+///   [`MidiMapping::bend_range`] semitones, on top of whatever shift is set.
+/// - Control changes sweep a parameter over its whole range; ratios sweep
+///   evenly in semitones.
+/// - Note-on sets the shift that puts the pitch currently detected in the
+///   input on the played note. Notes are ignored while the input is unvoiced.
+///
+/// Shifts are held within the pitch control's range rather than rejected.
+pub struct MidiControl {
+    pitch: PitchControl,
+    input_pitch: Arc<SharedPitch>,
+    channel: Option<u8>,
+    bend_range: f32,
+    controllers: Vec<(u8, Param)>,
+    notes: bool,
+    // Part of the current shift that came from pitch-bend, in semitones;
+    // less than the wheel asks for when the shift is held at the range.
+    bend: f32,
+}
+
+impl MidiControl {
+    /// Fails if the mapping names a parameter missing from `params`.
+    pub fn new(
+        pitch: PitchControl,
+        params: &ParamRegistry,
+        input_pitch: Arc<SharedPitch>,
+        mapping: &MidiMapping,
+    ) -> Result<Self> {
+        let controllers = mapping
+            .controllers
+            .iter()
+            .map(|cc| match params.get(&cc.param) {
+                Some(param) => Ok((cc.controller, param.clone())),
+                None => Err(anyhow!(
+                    "Unknown parameter \"{}\"; use one of {}",
+                    cc.param,
+                    params
+                        .iter()
+                        .map(|param| param.info().id)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )),
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            pitch,
+            input_pitch,
+            channel: mapping.channel,
+            bend_range: mapping.bend_range,
+            controllers,
+            notes: mapping.notes,
+            bend: 0.0,
+        })
+    }
+
+    /// Decodes and applies one raw message.
+    pub fn handle(&mut self, bytes: &[u8]) {
+        if let Some(message) = MidiMessage::parse(bytes) {
+            self.apply(message);
+        }
+    }
+
+    pub fn apply(&mut self, message: MidiMessage) {
+        if self
+            .channel
+            .is_some_and(|channel| channel != message.channel())
+        {
+            return;
+        }
+        match message {
+            MidiMessage::PitchBend { value, .. } => {
+                let bend = value as f32 / BEND_SCALE * self.bend_range;
+                let base = self.pitch.semitones() - self.bend;
+                self.bend = self.set_shift(base + bend) - base;
+            }
+            MidiMessage::ControlChange {
+                controller, value, ..
+            } => {
+                for (_, param) in self.controllers.iter().filter(|(cc, _)| *cc == controller) {
+                    param.set(cc_value(param.info(), value));
+                }
+            }
+            MidiMessage::NoteOn { note, .. } if self.notes => {
+                let Some(pitch) = self.input_pitch.get() else {
+                    return;
+                };
+                let (input, cents) = Note::from_frequency(pitch.frequency);
+                let target = note as i32 - input.0;
+                self.set_shift(target as f32 - cents / 100.0 + self.bend);
+            }
+            MidiMessage::NoteOn { .. } | MidiMessage::NoteOff { .. } => {}
+        }
+    }
+
+    /// Sets the shift, held within range, and returns the shift set.
+    fn set_shift(&self, semitones: f32) -> f32 {
+        let range = self.pitch.range();
+        let semitones = semitones.clamp(range.min_semitones, range.max_semitones);
+        // Always within range, so this can't fail.
+        let _ = self.pitch.apply(PitchCommand::Set(semitones));
+        semitones
+    }
+}
+
+/// Value of a parameter with its control change at `value` (0 to 127).
+fn cc_value(info: &ParamInfo, value: u8) -> f32 {
+    let position = value as f32 / 127.0;
+    if info.unit == Unit::Ratio {
+        let (min, max) = (ratio_to_semitones(info.min), ratio_to_semitones(info.max));
+        semitones_to_ratio(min + (max - min) * position)
+    } else {
+        info.min + (info.max - info.min) * position
+    }
+}
+
+/// Open MIDI input; messages are applied until it is dropped.
+pub struct MidiConnection {
+    _connection: MidiInputConnection<MidiControl>,
+    port_name: String,
+}
+
+impl MidiConnection {
+    /// Listens on the port picked by `selector`. [`DeviceSelector::Default`]
+    /// creates a virtual port that other programs, such as `aconnect` or a
+    /// sequencer, connect to instead.
+    pub fn open(selector: &DeviceSelector, control: MidiControl) -> Result<Self> {
+        let input = MidiInput::new(CLIENT_NAME).context("Couldn't open MIDI input")?;
+        let callback = |_timestamp: u64, bytes: &[u8], control: &mut MidiControl| {
+            control.handle(bytes);
+        };
+
+        if *selector == DeviceSelector::Default {
+            let port_name = format!("{CLIENT_NAME} input");
+            let connection = create_virtual(input, &port_name, callback, control)?;
+            return Ok(Self {
+                _connection: connection,
+                port_name,
+            });
+        }
+
+        let ports = input.ports();
+        let names = ports
+            .iter()
+            .map(|port| input.port_name(port))
+            .collect::<Result<Vec<_>, _>>()?;
+        let index = selector
+            .find(&names)
+            .with_context(|| format!("MIDI port (available: {})", names.join(", ")))?;
+        let connection = input
+            .connect(&ports[index], CLIENT_NAME, callback, control)
+            .map_err(|err| anyhow!("Couldn't connect to MIDI port {}: {err}", names[index]))?;
+        Ok(Self {
+            _connection: connection,
+            port_name: names.into_iter().nth(index).unwrap(),
+        })
+    }
+
+    pub fn port_name(&self) -> &str {
+        &self.port_name
+    }
+}
+
+#[cfg(unix)]
+fn create_virtual<F>(
+    input: MidiInput,
+    port_name: &str,
+    callback: F,
+    control: MidiControl,
+) -> Result<MidiInputConnection<MidiControl>>
+where
+    F: FnMut(u64, &[u8], &mut MidiControl) + Send + 'static,
+{
+    use midir::os::unix::VirtualInput;
+    input
+        .create_virtual(port_name, callback, control)
+        .map_err(|err| anyhow!("Couldn't create MIDI port {port_name}: {err}"))
+}
+
+#[cfg(not(unix))]
+fn create_virtual<F>(
+    _input: MidiInput,
+    _port_name: &str,
+    _callback: F,
+    _control: MidiControl,
+) -> Result<MidiInputConnection<MidiControl>>
+where
+    F: FnMut(u64, &[u8], &mut MidiControl) + Send + 'static,
+{
+    Err(anyhow!(
+        "Virtual MIDI ports aren't supported on this platform; name a port instead"
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::control::PitchRange;
+    use crate::mix::OUTPUT_GAIN;
+    use crate::pitch_detection::PitchTracker;
+    use crate::pitch_shifter::PITCH_FACTOR;
+    use crate::processor::AudioProcessor;
+
+    fn midi_control(input_pitch: Arc<SharedPitch>) -> (MidiControl, Param, Param) {
+        let mut params = ParamRegistry::new();
+        let pitch = params.add(PITCH_FACTOR);
+        let gain = params.add(OUTPUT_GAIN);
+        let mapping = MidiMapping {
+            controllers: vec!["7=gain".parse().unwrap(), "1=pitch".parse().unwrap()],
+            ..MidiMapping::default()
+        };
+        let control = PitchControl::new(pitch.clone(), PitchRange::symmetric(12.0));
+        let midi = MidiControl::new(control, &params, input_pitch, &mapping).unwrap();
+        (midi, pitch, gain)
+    }
+
+    #[test]
+    fn maps_bend_and_control_changes() {
+        let (mut midi, pitch, gain) = midi_control(Arc::default());
+        assert_eq!(
+            MidiMessage::parse(&[0xe3, 0x00, 0x60]),
+            Some(MidiMessage::PitchBend {
+                channel: 3,
+                value: 4096
+            })
+        );
+
+        // Half bend up is one semitone, on top of the shift already set.
+        pitch.set(semitones_to_ratio(5.0));
+        midi.handle(&[0xe0, 0x00, 0x60]);
+        assert!((ratio_to_semitones(pitch.get()) - 6.0).abs() < 1e-4);
+        midi.handle(&[0xe0, 0x00, 0x40]);
+        assert!((ratio_to_semitones(pitch.get()) - 5.0).abs() < 1e-4);
+        // A bend held at the edge of the range returns to the same shift.
+        pitch.set(semitones_to_ratio(11.0));
+        midi.handle(&[0xe0, 0x7f, 0x7f]);
+        assert!((ratio_to_semitones(pitch.get()) - 12.0).abs() < 1e-4);
+        midi.handle(&[0xe0, 0x00, 0x40]);
+        assert!((ratio_to_semitones(pitch.get()) - 11.0).abs() < 1e-4);
+
+        midi.handle(&[0xb0, 7, 127]);
+        assert_eq!(gain.get(), OUTPUT_GAIN.max);
+        midi.handle(&[0xb0, 7, 0]);
+        assert_eq!(gain.get(), OUTPUT_GAIN.min);
+        // Ratios sweep evenly in semitones, so the middle is close to unshifted.
+        midi.handle(&[0xb0, 1, 64]);
+        assert!(ratio_to_semitones(pitch.get()).abs() < 0.3);
+    }
+
+    #[test]
+    fn note_on_shifts_input_to_the_played_note() {
+        // A3 at 220 Hz in the input.
+        let mut tracker = PitchTracker::new();
+        tracker.prepare(48_000, 1);
+        let mut input: Vec<f32> = (0..9600)
+            .map(|i| (2.0 * std::f32::consts::PI * 220.0 * i as f32 / 48_000.0).sin())
+            .collect();
+        tracker.process(&mut input);
+        let (mut midi, pitch, _) = midi_control(tracker.shared());
+
+        // C4 is three semitones up; notes on other channels are ignored.
+        midi.channel = Some(0);
+        midi.handle(&[0x91, 72, 100]);
+        assert_eq!(pitch.get(), 1.0);
+        midi.handle(&[0x90, 60, 100]);
+        assert!((ratio_to_semitones(pitch.get()) - 3.0).abs() < 0.05);
+        // Out of range notes stop at the edge of the range.
+        midi.handle(&[0x90, 96, 100]);
+        assert!((ratio_to_semitones(pitch.get()) - 12.0).abs() < 1e-4);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    #[ignore = "needs an ALSA sequencer"]
+    fn receives_from_a_virtual_port() {
+        use midir::{MidiOutput, os::unix::VirtualOutput};
+        use std::{thread, time::Duration};
+
+        let (midi, _, gain) = midi_control(Arc::default());
+        let output = MidiOutput::new("audio_effects test").unwrap();
+        let mut port = output.create_virtual("controller").unwrap();
+        let name = DeviceSelector::Name("controller".to_string());
+        let connection = MidiConnection::open(&name, midi).unwrap();
+        assert!(connection.port_name().contains("controller"));
+
+        port.send(&[0xb0, 7, 127]).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(gain.get(), OUTPUT_GAIN.max);
+    }
+}
@@ -0,0 +1,357 @@
+use crate::params::{Param, ParamInfo, Unit};
+use crate::pitch_shifter::DEFAULT_GLIDE;
+use crate::processor::AudioProcessor;
+use crate::smoothing::SmoothedParam;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// How far ahead the limiter looks for peaks; also its attack time.
+pub const LOOKAHEAD: Duration = Duration::from_millis(5);
+/// Time constant of the limiter's recovery once a peak has passed.
+const RELEASE: Duration = Duration::from_millis(100);
+/// Frames processed at a time, so the dry history can be sized up front.
+const CHUNK_FRAMES: usize = 256;
+/// Rate assumed until [`AudioProcessor::prepare`] is called.
+const DEFAULT_SAMPLE_RATE: u32 = 48_000;
+
+/// Name of the [`Limiter`] in a live effect chain. Presets keep it last so
+/// nothing outside [-1, 1] reaches the device.
+pub const LIMITER_NAME: &str = "limiter";
+
+/// Share of processed signal read by [`DryWet`].
+pub const MIX: ParamInfo = ParamInfo::new("mix", "Mix", 0.0, 1.0, 1.0);
+/// Gain read by [`Limiter`].
+pub const OUTPUT_GAIN: ParamInfo =
+    ParamInfo::new("gain", "Output gain", -60.0, 24.0, 0.0).with_unit(Unit::Decibels);
+
+/// Blends a processor's output with its unprocessed input.
+///
+/// The dry signal is delayed by the processor's latency so the two line up.
+/// `mix` runs from 0 (dry only) to 1 (processed only) and is read on every
+/// block.
+pub struct DryWet<P> {
+    processor: P,
+    mix: Param,
+    smoothed: SmoothedParam,
+    channels: usize,
+    // Interleaved input, indexed by absolute sample position.
+    dry: Vec<f32>,
+    mask: usize,
+    written: usize,
+}
+
+impl<P: AudioProcessor> DryWet<P> {
+    pub fn new(processor: P, mix: Param) -> Self {
+        let initial = mix.get();
+        let mut dry_wet = Self {
+            processor,
+            mix,
+            smoothed: SmoothedParam::new(initial, DEFAULT_GLIDE, DEFAULT_SAMPLE_RATE),
+            channels: 1,
+            dry: Vec::new(),
+            mask: 0,
+            written: 0,
+        };
+        dry_wet.allocate();
+        dry_wet
+    }
+
+    pub fn processor(&self) -> &P {
+        &self.processor
+    }
+
+    fn allocate(&mut self) {
+        let len = ((self.processor.latency() + CHUNK_FRAMES) * self.channels).next_power_of_two();
+        self.dry = vec![0.0; len];
+        self.mask = len - 1;
+        self.written = 0;
+    }
+
+    fn process_chunk(&mut self, chunk: &mut [f32]) {
+        let start = self.written * self.channels;
+        for (i, &sample) in chunk.iter().enumerate() {
+            self.dry[(start + i) & self.mask] = sample;
+        }
+        self.processor.process(chunk);
+
+        let delay = self.processor.latency() * self.channels;
+        for (frame, samples) in chunk.chunks_exact_mut(self.channels).enumerate() {
+            let mix = self.smoothed.next_value();
+            for (channel, wet) in samples.iter_mut().enumerate() {
+                let position = (start + frame * self.channels + channel).checked_sub(delay);
+                let dry = position.map_or(0.0, |position| self.dry[position & self.mask]);
+                *wet = dry + (*wet - dry) * mix;
+            }
+        }
+        self.written += chunk.len() / self.channels;
+    }
+}
+
+impl<P: AudioProcessor> AudioProcessor for DryWet<P> {
+    fn prepare(&mut self, sample_rate: u32, channels: usize) {
+        self.processor.prepare(sample_rate, channels);
+        self.channels = channels;
+        self.smoothed.set_sample_rate(sample_rate);
+        self.allocate();
+    }
+
+    fn process(&mut self, block: &mut [f32]) {
+        let mix = self.mix.get().clamp(0.0, 1.0);
+        if mix != self.smoothed.target() {
+            self.smoothed.set_target(mix);
+        }
+        for chunk in block.chunks_mut(CHUNK_FRAMES * self.channels) {
+            self.process_chunk(chunk);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.processor.reset();
+        self.dry.fill(0.0);
+        self.written = 0;
+        let mix = self.smoothed.target();
+        self.smoothed.reset(mix);
+    }
+
+    fn latency(&self) -> usize {
+        self.processor.latency()
+    }
+}
+
+/// Output gain followed by a look-ahead brickwall limiter.
+///
+/// The signal is delayed by [`LOOKAHEAD`] so the gain can come down before
+/// a peak arrives rather than clipping it. Gain reduction is shared by all
+/// channels to keep the stereo image, and whatever rounding leaves over the
+/// ceiling is clamped, so nothing outside [-1, 1] leaves the limiter.
+pub struct Limiter {
+    gain_db: Param,
+    gain: SmoothedParam,
+    ceiling: f32,
+    sample_rate: u32,
+    channels: usize,
+    lookahead: usize,
+    release: f32,
+    // Delayed interleaved samples, `lookahead` frames long.
+    delay: Vec<f32>,
+    position: usize,
+    // Minimum required gain over the look-ahead window and the frame
+    // entering it, as (frame, gain).
+    minimum: VecDeque<(usize, f32)>,
+    // Box filter over the last `lookahead` held gains.
+    held: Vec<f32>,
+    held_sum: f64,
+    released: f32,
+    frame: usize,
+}
+
+impl Limiter {
+    /// Applies `gain_db` decibels of gain, read on every block, before
+    /// limiting.
+    pub fn new(gain_db: Param) -> Self {
+        let initial = gain_db.get();
+        let mut limiter = Self {
+            gain_db,
+            gain: SmoothedParam::new(initial, DEFAULT_GLIDE, DEFAULT_SAMPLE_RATE),
+            ceiling: 1.0,
+            sample_rate: 0,
+            channels: 1,
+            lookahead: 0,
+            release: 0.0,
+            delay: Vec::new(),
+            position: 0,
+            minimum: VecDeque::new(),
+            held: Vec::new(),
+            held_sum: 0.0,
+            released: 1.0,
+            frame: 0,
+        };
+        limiter.prepare(DEFAULT_SAMPLE_RATE, 1);
+        limiter
+    }
+
+    /// Limits to `ceiling` instead of full scale.
+    pub fn with_ceiling(mut self, ceiling: f32) -> Self {
+        assert!(0.0 < ceiling && ceiling <= 1.0, "ceiling must be in (0, 1]");
+        self.ceiling = ceiling;
+        self
+    }
+
+    /// Gain that brings the loudest sample of `frame` down to the ceiling.
+    fn required_gain(&self, frame: &[f32]) -> f32 {
+        let peak = frame.iter().fold(0.0_f32, |peak, s| peak.max(s.abs()));
+        if peak > self.ceiling {
+            self.ceiling / peak
+        } else {
+            1.0
+        }
+    }
+
+    /// Gain for the frame leaving the delay line, given the one entering.
+    ///
+    /// The minimum over the window is averaged over the same window, so the
+    /// gain has reached every peak's requirement by the time it comes out.
+    fn next_gain(&mut self, required: f32) -> f32 {
+        while self
+            .minimum
+            .back()
+            .is_some_and(|&(_, gain)| gain >= required)
+        {
+            self.minimum.pop_back();
+        }
+        self.minimum.push_back((self.frame, required));
+        while self
+            .minimum
+            .front()
+            .is_some_and(|&(frame, _)| frame + self.lookahead < self.frame)
+        {
+            self.minimum.pop_front();
+        }
+        let window_minimum = self.minimum.front().map_or(1.0, |&(_, gain)| gain);
+
+        // Recover slowly, but never above what the window needs.
+        self.released = window_minimum.min(1.0 - (1.0 - self.released) * self.release);
+
+        let slot = self.frame % self.lookahead;
+        self.held_sum += (self.released - self.held[slot]) as f64;
+        self.held[slot] = self.released;
+        self.frame += 1;
+        (self.held_sum / self.lookahead as f64) as f32
+    }
+}
+
+impl AudioProcessor for Limiter {
+    fn prepare(&mut self, sample_rate: u32, channels: usize) {
+        self.channels = channels;
+        self.gain.set_sample_rate(sample_rate);
+        if sample_rate != self.sample_rate || self.delay.len() != self.lookahead * channels {
+            self.sample_rate = sample_rate;
+            self.lookahead = ((LOOKAHEAD.as_secs_f32() * sample_rate as f32) as usize).max(1);
+            self.release = (-1.0 / (RELEASE.as_secs_f32() * sample_rate as f32)).exp();
+            self.delay = vec![0.0; self.lookahead * channels];
+            self.held = vec![1.0; self.lookahead];
+            self.minimum = VecDeque::with_capacity(self.lookahead + 2);
+        }
+        self.reset();
+    }
+
+    fn process(&mut self, block: &mut [f32]) {
+        let gain_db = self.gain_db.get();
+        if gain_db != self.gain.target() {
+            self.gain.set_target(gain_db);
+        }
+
+        let channels = self.channels;
+        for frame in block.chunks_exact_mut(channels) {
+            let gain = 10.0_f32.powf(self.gain.next_value() / 20.0);
+            for sample in frame.iter_mut() {
+                *sample *= gain;
+            }
+            let reduction = self.next_gain(self.required_gain(frame));
+
+            // Swap the frame with the one leaving the delay line.
+            let delayed = &mut self.delay[self.position..self.position + channels];
+            for (sample, delayed) in frame.iter_mut().zip(delayed) {
+                let input = *sample;
+                *sample = (*delayed * reduction).clamp(-self.ceiling, self.ceiling);
+                *delayed = input;
+            }
+            self.position = (self.position + channels) % self.delay.len();
+        }
+    }
+
+    fn reset(&mut self) {
+        self.delay.fill(0.0);
+        self.position = 0;
+        self.minimum.clear();
+        self.held.fill(1.0);
+        self.held_sum = self.lookahead as f64;
+        self.released = 1.0;
+        self.frame = 0;
+        let gain_db = self.gain.target();
+        self.gain.reset(gain_db);
+    }
+
+    fn latency(&self) -> usize {
+        self.lookahead
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    /// Delays its input by a fixed number of samples and inverts it.
+    struct Invert {
+        history: VecDeque<f32>,
+    }
+
+    impl AudioProcessor for Invert {
+        fn prepare(&mut self, _sample_rate: u32, _channels: usize) {
+            self.history = VecDeque::from(vec![0.0; 3]);
+        }
+
+        fn process(&mut self, block: &mut [f32]) {
+            for sample in block {
+                self.history.push_back(-*sample);
+                *sample = self.history.pop_front().unwrap();
+            }
+        }
+
+        fn reset(&mut self) {}
+
+        fn latency(&self) -> usize {
+            3
+        }
+    }
+
+    #[test]
+    fn blends_latency_compensated_dry_signal() {
+        let input: Vec<f32> = (0..1000).map(|i| (i as f32 * 0.01).sin()).collect();
+        for &(mix, scale) in &[(0.0, 1.0), (1.0, -1.0), (0.25, 0.5)] {
+            let mut dry_wet = DryWet::new(
+                Invert {
+                    history: VecDeque::new(),
+                },
+                Param::new(MIX.with_default(mix)),
+            );
+            dry_wet.prepare(48_000, 1);
+            let mut output = input.clone();
+            dry_wet.process(&mut output);
+            for i in 3..input.len() {
+                assert!((output[i] - scale * input[i - 3]).abs() < 1e-5, "mix {mix}");
+            }
+        }
+    }
+
+    #[test]
+    fn keeps_loud_input_within_full_scale() {
+        let mut limiter = Limiter::new(Param::new(OUTPUT_GAIN.with_default(12.0)));
+        limiter.prepare(48_000, 2);
+        let latency = limiter.latency();
+
+        let input: Vec<f32> = (0..48_000)
+            .flat_map(|i| {
+                let s = 0.9 * (2.0 * PI * 220.0 * i as f32 / 48_000.0).sin();
+                [s, -0.5 * s]
+            })
+            .collect();
+        let mut output = input.clone();
+        for block in output.chunks_mut(512) {
+            limiter.process(block);
+        }
+        assert!(output.iter().all(|s| s.abs() <= 1.0));
+        let peak = output[24_000..].iter().fold(0.0_f32, |p, s| p.max(s.abs()));
+        assert!(peak > 0.95, "{peak}");
+
+        // Quiet input comes out delayed but otherwise untouched.
+        let mut limiter = Limiter::new(Param::new(OUTPUT_GAIN));
+        limiter.prepare(48_000, 2);
+        let mut output = input.iter().map(|s| s * 0.5).collect::<Vec<_>>();
+        limiter.process(&mut output);
+        for i in latency * 2..output.len() {
+            assert!((output[i] - 0.5 * input[i - latency * 2]).abs() < 1e-6);
+        }
+    }
+}
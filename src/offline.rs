@@ -0,0 +1,242 @@
+use crate::channels::PerChannel;
+use crate::params::{Param, ParamInfo};
+use crate::pitch_shifter::{
+    Engine, FORMANT_FACTOR, PITCH_FACTOR, PitchShifter, ratio_to_semitones,
+};
+use crate::processor::AudioProcessor;
+use crate::psola::PsolaShifter;
+use crate::resample;
+use crate::wav::{self, AudioBuffer};
+use crate::wsola::{self, TIME_RATIO_RANGE, WsolaShifter};
+use anyhow::{Result, bail};
+use std::path::Path;
+
+/// Frames handed to the processor per call, matching a typical device callback.
+const BLOCK_FRAMES: usize = 512;
+
+/// Settings for [`process_file`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProcessOptions {
+    pub engine: Engine,
+    pub pitch_factor: f32,
+    /// Output duration relative to the input; tempo changes always use WSOLA.
+    pub time_ratio: f32,
+    /// Preserve formants and move them by this ratio; `None` lets them
+    /// follow the pitch. Only the vocoder engine supports this.
+    pub formant_factor: Option<f32>,
+    /// Sample rate of the written file; `None` keeps the input's rate.
+    pub sample_rate: Option<u32>,
+}
+
+impl Default for ProcessOptions {
+    fn default() -> Self {
+        Self {
+            engine: Engine::Vocoder,
+            pitch_factor: 1.0,
+            time_ratio: 1.0,
+            formant_factor: None,
+            sample_rate: None,
+        }
+    }
+}
+
+/// Runs `buffer` through `processor` in device-sized blocks.
+///
+/// The processor's latency is compensated, so the result lines up with the
+/// input and has the same length.
+pub fn process_buffer(buffer: &AudioBuffer, processor: &mut dyn AudioProcessor) -> AudioBuffer {
+    let channels = buffer.channels as usize;
+    processor.prepare(buffer.sample_rate, channels);
+    let latency = processor.latency();
+
+    // Pad with silence to flush the processor's internal delay.
+    let mut samples = buffer.samples.clone();
+    samples.resize(buffer.samples.len() + latency * channels, 0.0);
+
+    for block in samples.chunks_mut(BLOCK_FRAMES * channels) {
+        processor.process(block);
+    }
+    samples.drain(..latency * channels);
+
+    AudioBuffer {
+        samples,
+        channels: buffer.channels,
+        sample_rate: buffer.sample_rate,
+    }
+}
+
+/// Pitch-shifts every channel of `buffer` independently.
+pub fn pitch_shift_buffer(buffer: &AudioBuffer, pitch_factor: f32) -> AudioBuffer {
+    let options = ProcessOptions {
+        pitch_factor,
+        ..ProcessOptions::default()
+    };
+    process_buffer(buffer, pitch_shifters(&options).as_mut())
+}
+
+/// Builds the per-channel pitch shifter described by `options`.
+fn pitch_shifters(options: &ProcessOptions) -> Box<dyn AudioProcessor> {
+    let pitch_factor = fixed(PITCH_FACTOR, options.pitch_factor);
+    match options.engine {
+        Engine::Vocoder => {
+            let formant_factor = options.formant_factor.map(|f| fixed(FORMANT_FACTOR, f));
+            Box::new(PerChannel::new(move || {
+                let shifter = PitchShifter::new(pitch_factor.clone());
+                match &formant_factor {
+                    Some(formant_factor) => shifter.with_formants(formant_factor.clone()),
+                    None => shifter,
+                }
+            }))
+        }
+        Engine::Psola => Box::new(PerChannel::new(move || {
+            PsolaShifter::new(pitch_factor.clone())
+        })),
+        Engine::Wsola => Box::new(PerChannel::new(move || {
+            WsolaShifter::new(pitch_factor.clone())
+        })),
+    }
+}
+
+/// A parameter held at `value`, which may lie outside the live range.
+fn fixed(info: ParamInfo, value: f32) -> Param {
+    Param::new(info.with_range(value, value).with_default(value))
+}
+
+/// Changes the duration of `buffer` by `time_ratio` and its pitch by
+/// `pitch_factor` with WSOLA.
+pub fn stretch_buffer(buffer: &AudioBuffer, time_ratio: f32, pitch_factor: f32) -> AudioBuffer {
+    AudioBuffer {
+        samples: wsola::stretch(
+            &buffer.samples,
+            buffer.channels as usize,
+            buffer.sample_rate,
+            time_ratio,
+            pitch_factor,
+        ),
+        channels: buffer.channels,
+        sample_rate: buffer.sample_rate,
+    }
+}
+
+/// Converts `buffer` to `sample_rate` with the band-limited resampler.
+pub fn resample_buffer(buffer: &AudioBuffer, sample_rate: u32) -> AudioBuffer {
+    AudioBuffer {
+        samples: resample::resample(
+            &buffer.samples,
+            buffer.channels as usize,
+            buffer.sample_rate,
+            sample_rate,
+        ),
+        channels: buffer.channels,
+        sample_rate,
+    }
+}
+
+/// Reads `input`, processes it according to `options` and writes `output`
+/// in the input's sample format.
+pub fn process_file(input: &Path, output: &Path, options: &ProcessOptions) -> Result<()> {
+    if options.engine != Engine::Vocoder && options.formant_factor.is_some() {
+        bail!("Formant control needs the vocoder engine");
+    }
+    let (min, max) = TIME_RATIO_RANGE;
+    if !(min..=max).contains(&options.time_ratio) {
+        bail!(
+            "Time ratio must be between {min} and {max}, got {}",
+            options.time_ratio
+        );
+    }
+    check_factor(PITCH_FACTOR, options.pitch_factor)?;
+    if let Some(formant_factor) = options.formant_factor {
+        check_factor(FORMANT_FACTOR, formant_factor)?;
+    }
+    if options.sample_rate == Some(0) {
+        bail!("Sample rate must be above 0 Hz");
+    }
+    let (buffer, spec) = wav::read_wav(input)?;
+    let mut processed = match options.engine {
+        // WSOLA stretches and shifts in one pass.
+        Engine::Wsola => stretch_buffer(&buffer, options.time_ratio, options.pitch_factor),
+        _ => {
+            let shifted = process_buffer(&buffer, pitch_shifters(options).as_mut());
+            if options.time_ratio == 1.0 {
+                shifted
+            } else {
+                stretch_buffer(&shifted, options.time_ratio, 1.0)
+            }
+        }
+    };
+    if let Some(sample_rate) = options.sample_rate {
+        processed = resample_buffer(&processed, sample_rate);
+    }
+    wav::write_wav(output, &processed, spec)
+}
+
+/// Rejects factors outside the range `info` supports, naming it in semitones.
+fn check_factor(info: ParamInfo, factor: f32) -> Result<()> {
+    if !(info.min..=info.max).contains(&factor) {
+        bail!(
+            "{} shift must be between {:+.0} and {:+.0} semitones",
+            info.name,
+            ratio_to_semitones(info.min),
+            ratio_to_semitones(info.max)
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    #[test]
+    fn keeps_channels_separate_and_aligned() {
+        let frames = 16_384;
+        let mut samples = Vec::with_capacity(frames * 2);
+        for i in 0..frames {
+            samples.push(0.5 * (2.0 * PI * 440.0 * i as f32 / 48_000.0).sin());
+            samples.push(0.0);
+        }
+        let buffer = AudioBuffer {
+            samples,
+            channels: 2,
+            sample_rate: 48_000,
+        };
+
+        let shifted = pitch_shift_buffer(&buffer, 1.0);
+        assert_eq!(shifted.samples.len(), buffer.samples.len());
+
+        for frame in 4096..frames - 4096 {
+            let left = shifted.samples[frame * 2];
+            let right = shifted.samples[frame * 2 + 1];
+            assert!((left - buffer.samples[frame * 2]).abs() < 1e-3);
+            assert_eq!(right, 0.0);
+        }
+    }
+
+    #[test]
+    fn rejects_unsupported_options() {
+        let path = Path::new("missing.wav");
+        for options in [
+            ProcessOptions {
+                time_ratio: f32::INFINITY,
+                ..ProcessOptions::default()
+            },
+            ProcessOptions {
+                pitch_factor: crate::semitones_to_ratio(-200.0),
+                ..ProcessOptions::default()
+            },
+            ProcessOptions {
+                formant_factor: Some(f32::NAN),
+                ..ProcessOptions::default()
+            },
+            ProcessOptions {
+                sample_rate: Some(0),
+                ..ProcessOptions::default()
+            },
+        ] {
+            let err = process_file(path, path, &options).unwrap_err();
+            assert!(!err.to_string().contains("missing.wav"), "{err}");
+        }
+    }
+}
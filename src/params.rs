@@ -0,0 +1,260 @@
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+/// What a parameter's value measures, for display.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Unit {
+    #[default]
+    None,
+    /// A frequency ratio, e.g. 2 for an octave up.
+    Ratio,
+    Semitones,
+    Decibels,
+    Milliseconds,
+}
+
+impl fmt::Display for Unit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::None => "",
+            Self::Ratio => "x",
+            Self::Semitones => "st",
+            Self::Decibels => "dB",
+            Self::Milliseconds => "ms",
+        })
+    }
+}
+
+/// Describes a parameter: a stable id for presets and mappings, a name for
+/// display, and the values it accepts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ParamInfo {
+    pub id: &'static str,
+    pub name: &'static str,
+    pub min: f32,
+    pub max: f32,
+    pub default: f32,
+    pub unit: Unit,
+}
+
+impl ParamInfo {
+    /// # Panics
+    ///
+    /// If `min` is above `max` or either is NaN.
+    pub const fn new(
+        id: &'static str,
+        name: &'static str,
+        min: f32,
+        max: f32,
+        default: f32,
+    ) -> Self {
+        assert!(min <= max, "parameter range is empty");
+        Self {
+            id,
+            name,
+            min,
+            max,
+            default,
+            unit: Unit::None,
+        }
+    }
+
+    pub const fn with_unit(mut self, unit: Unit) -> Self {
+        self.unit = unit;
+        self
+    }
+
+    /// Narrows or widens the accepted values.
+    ///
+    /// # Panics
+    ///
+    /// If `min` is above `max` or either is NaN.
+    pub const fn with_range(mut self, min: f32, max: f32) -> Self {
+        assert!(min <= max, "parameter range is empty");
+        self.min = min;
+        self.max = max;
+        self
+    }
+
+    pub const fn with_default(mut self, default: f32) -> Self {
+        self.default = default;
+        self
+    }
+
+    /// `value` limited to the accepted range.
+    pub fn clamp(&self, value: f32) -> f32 {
+        value.clamp(self.min, self.max)
+    }
+}
+
+struct ParamState {
+    info: ParamInfo,
+    // f32 bits.
+    value: AtomicU32,
+    changed: AtomicBool,
+}
+
+/// Handle to one parameter value, shared by the control and audio threads.
+///
+/// Reads and writes are single atomic operations, so either side can use
+/// them at any time without waiting; the audio thread reads once per block.
+/// Every write marks the parameter changed until
+/// [`ParamRegistry::changes`] reports it.
+#[derive(Clone)]
+pub struct Param(Arc<ParamState>);
+
+impl Param {
+    /// A parameter set to its default.
+    ///
+    /// # Panics
+    ///
+    /// If the range in `info` is empty or NaN.
+    pub fn new(info: ParamInfo) -> Self {
+        assert!(
+            info.min <= info.max,
+            "parameter {} has an empty range",
+            info.id
+        );
+        Self(Arc::new(ParamState {
+            info,
+            value: AtomicU32::new(info.clamp(info.default).to_bits()),
+            changed: AtomicBool::new(false),
+        }))
+    }
+
+    pub fn info(&self) -> &ParamInfo {
+        &self.0.info
+    }
+
+    pub fn get(&self) -> f32 {
+        f32::from_bits(self.0.value.load(Ordering::Relaxed))
+    }
+
+    /// Stores `value`, limited to the parameter's range, and returns what
+    /// was stored. Values that aren't numbers are ignored.
+    pub fn set(&self, value: f32) -> f32 {
+        if value.is_nan() {
+            return self.get();
+        }
+        let value = self.0.info.clamp(value);
+        self.0.value.store(value.to_bits(), Ordering::Relaxed);
+        self.0.changed.store(true, Ordering::Release);
+        value
+    }
+
+    pub fn reset(&self) {
+        self.set(self.0.info.default);
+    }
+
+    /// Whether the value was written since the last call.
+    fn take_changed(&self) -> bool {
+        self.0.changed.swap(false, Ordering::Acquire)
+    }
+}
+
+impl fmt::Debug for Param {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Param")
+            .field("id", &self.0.info.id)
+            .field("value", &self.get())
+            .finish()
+    }
+}
+
+impl fmt::Display for Param {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {:.2}{}",
+            self.0.info.name,
+            self.get(),
+            self.0.info.unit
+        )
+    }
+}
+
+/// The parameters a user can control, looked up by id.
+///
+/// Effects are handed [`Param`] handles from the registry when they are
+/// built; the control thread keeps the registry to change values by id and
+/// to hear about changes made elsewhere, such as by MIDI.
+#[derive(Clone, Default)]
+pub struct ParamRegistry {
+    params: Vec<Param>,
+}
+
+impl ParamRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a parameter set to its default and returns its handle.
+    ///
+    /// # Panics
+    ///
+    /// If a parameter with the same id is already registered.
+    pub fn add(&mut self, info: ParamInfo) -> Param {
+        assert!(
+            self.get(info.id).is_none(),
+            "parameter {} registered twice",
+            info.id
+        );
+        let param = Param::new(info);
+        self.params.push(param.clone());
+        param
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Param> {
+        self.params.iter().find(|param| param.info().id == id)
+    }
+
+    /// Parameters in the order they were added.
+    pub fn iter(&self) -> impl Iterator<Item = &Param> {
+        self.params.iter()
+    }
+
+    /// Parameters written since the previous call, from any thread.
+    pub fn changes(&self) -> impl Iterator<Item = &Param> {
+        self.params.iter().filter(|param| param.take_changed())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    const GAIN: ParamInfo =
+        ParamInfo::new("gain", "Gain", -12.0, 12.0, 0.0).with_unit(Unit::Decibels);
+
+    #[test]
+    fn clamps_writes_and_reports_changes() {
+        let mut registry = ParamRegistry::new();
+        let gain = registry.add(GAIN);
+        let mix = registry.add(ParamInfo::new("mix", "Mix", 0.0, 1.0, 1.0));
+        assert_eq!(registry.changes().count(), 0);
+
+        let audio = {
+            let gain = gain.clone();
+            thread::spawn(move || gain.set(30.0))
+        };
+        assert_eq!(audio.join().unwrap(), 12.0);
+        assert_eq!(registry.get("gain").unwrap().get(), 12.0);
+        assert_eq!(gain.set(f32::NAN), 12.0);
+        let changed: Vec<_> = registry.changes().map(|p| p.info().id).collect();
+        assert_eq!(changed, ["gain"]);
+        assert_eq!(registry.changes().count(), 0);
+
+        mix.set(0.25);
+        gain.reset();
+        assert_eq!(registry.changes().count(), 2);
+        assert_eq!(gain.to_string(), "Gain 0.00dB");
+    }
+
+    #[test]
+    #[should_panic(expected = "parameter range is empty")]
+    fn rejects_nan_ranges() {
+        Param::new(GAIN.with_range(f32::NAN, 1.0));
+    }
+}
@@ -0,0 +1,430 @@
+use crate::processor::AudioProcessor;
+use rustfft::num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// Normalised difference below which a lag counts as the period.
+const DEFAULT_THRESHOLD: f32 = 0.15;
+/// Mean-square level below which a window is treated as silence.
+const SILENCE: f64 = 1e-8;
+/// Concert pitch: frequency of A4, MIDI note 69.
+pub const A4_FREQUENCY: f32 = 440.0;
+/// Range [`PitchTracker`] listens for unless told otherwise: low B on a
+/// five-string bass to the top of a soprano.
+const DEFAULT_RANGE: (f32, f32) = (30.0, 1500.0);
+/// Detections per second made by [`PitchTracker`].
+const UPDATES_PER_SECOND: u32 = 50;
+/// Rate assumed until [`AudioProcessor::prepare`] is called.
+const DEFAULT_SAMPLE_RATE: u32 = 48_000;
+
+/// A note of the equal-tempered scale, numbered as in MIDI (60 is middle C).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Note(pub i32);
+
+impl Note {
+    pub(crate) const NAMES: [&str; 12] = [
+        "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+    ];
+
+    /// Nearest note to `frequency` and the deviation from it in cents.
+    pub fn from_frequency(frequency: f32) -> (Note, f32) {
+        let position = 69.0 + 12.0 * (frequency / A4_FREQUENCY).log2();
+        let note = position.round();
+        (Note(note as i32), 100.0 * (position - note))
+    }
+
+    pub fn frequency(self) -> f32 {
+        A4_FREQUENCY * 2.0_f32.powf((self.0 - 69) as f32 / 12.0)
+    }
+
+    /// Position within the octave, 0 for C up to 11 for B.
+    pub fn pitch_class(self) -> usize {
+        self.0.rem_euclid(12) as usize
+    }
+
+    pub fn octave(self) -> i32 {
+        self.0.div_euclid(12) - 1
+    }
+}
+
+impl fmt::Display for Note {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", Note::NAMES[self.pitch_class()], self.octave())
+    }
+}
+
+/// Fundamental frequency estimate from [`PitchDetector::detect`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pitch {
+    pub frequency: f32,
+    /// How periodic the window is, from 0 (noise) to 1 (perfectly periodic).
+    pub clarity: f32,
+}
+
+impl Pitch {
+    /// Period in samples at `sample_rate`.
+    pub fn period(&self, sample_rate: u32) -> f32 {
+        sample_rate as f32 / self.frequency
+    }
+
+    /// Nearest equal-tempered note.
+    pub fn note(&self) -> Note {
+        Note::from_frequency(self.frequency).0
+    }
+
+    /// Deviation from [`Pitch::note`] in cents, between -50 and +50.
+    pub fn cents(&self) -> f32 {
+        Note::from_frequency(self.frequency).1
+    }
+}
+
+/// YIN fundamental-frequency estimator.
+///
+/// The difference function is computed through FFT autocorrelation, so a
+/// detection costs O(n log n) in the window length and is cheap enough to
+/// run from the audio callback.
+pub struct PitchDetector {
+    sample_rate: f32,
+    min_lag: usize,
+    max_lag: usize,
+    threshold: f32,
+    fft: Arc<dyn Fft<f32>>,
+    ifft: Arc<dyn Fft<f32>>,
+    window_spectrum: Vec<Complex<f32>>,
+    signal_spectrum: Vec<Complex<f32>>,
+    scratch: Vec<Complex<f32>>,
+    energy: Vec<f64>,
+    difference: Vec<f32>,
+}
+
+impl PitchDetector {
+    /// Creates a detector for fundamentals between `min_frequency` and
+    /// `max_frequency` Hz.
+    pub fn new(sample_rate: u32, min_frequency: f32, max_frequency: f32) -> Self {
+        assert!(
+            0.0 < min_frequency && min_frequency < max_frequency,
+            "frequency range must be positive and non-empty"
+        );
+        let min_lag = ((sample_rate as f32 / max_frequency) as usize).max(2);
+        let max_lag = (sample_rate as f32 / min_frequency).ceil() as usize + 1;
+
+        let size = (2 * max_lag).next_power_of_two();
+        let mut planner = FftPlanner::new();
+        let fft = planner.plan_fft_forward(size);
+        let ifft = planner.plan_fft_inverse(size);
+        let scratch_len = fft
+            .get_inplace_scratch_len()
+            .max(ifft.get_inplace_scratch_len());
+
+        Self {
+            sample_rate: sample_rate as f32,
+            min_lag,
+            max_lag,
+            threshold: DEFAULT_THRESHOLD,
+            fft,
+            ifft,
+            window_spectrum: vec![Complex::new(0.0, 0.0); size],
+            signal_spectrum: vec![Complex::new(0.0, 0.0); size],
+            scratch: vec![Complex::new(0.0, 0.0); scratch_len],
+            energy: vec![0.0; 2 * max_lag + 1],
+            difference: vec![0.0; max_lag + 1],
+        }
+    }
+
+    /// Samples [`PitchDetector::detect`] looks at: two periods of the lowest frequency.
+    pub fn window_len(&self) -> usize {
+        2 * self.max_lag
+    }
+
+    /// Sets how periodic a window must be to report a pitch; lower is stricter.
+    pub fn set_threshold(&mut self, threshold: f32) {
+        self.threshold = threshold;
+    }
+
+    /// Estimates the fundamental of the last [`PitchDetector::window_len`]
+    /// samples, or `None` for silence, noise, or too little audio.
+    pub fn detect(&mut self, samples: &[f32]) -> Option<Pitch> {
+        let len = self.window_len();
+        let width = self.max_lag;
+        let samples = samples.get(samples.len().checked_sub(len)?..)?;
+
+        self.energy[0] = 0.0;
+        for (i, &sample) in samples.iter().enumerate() {
+            self.energy[i + 1] = self.energy[i] + (sample as f64).powi(2);
+        }
+        if self.energy[width] / (width as f64) < SILENCE {
+            return None;
+        }
+
+        // Autocorrelation of the first `width` samples against the whole window.
+        self.window_spectrum.fill(Complex::new(0.0, 0.0));
+        self.signal_spectrum.fill(Complex::new(0.0, 0.0));
+        for (i, &sample) in samples.iter().enumerate() {
+            if i < width {
+                self.window_spectrum[i].re = sample;
+            }
+            self.signal_spectrum[i].re = sample;
+        }
+        self.fft
+            .process_with_scratch(&mut self.window_spectrum, &mut self.scratch);
+        self.fft
+            .process_with_scratch(&mut self.signal_spectrum, &mut self.scratch);
+        for (window, signal) in self.window_spectrum.iter_mut().zip(&self.signal_spectrum) {
+            *window = window.conj() * signal;
+        }
+        self.ifft
+            .process_with_scratch(&mut self.window_spectrum, &mut self.scratch);
+        let scale = 1.0 / self.window_spectrum.len() as f64;
+
+        // Cumulative-mean-normalised difference function.
+        self.difference[0] = 1.0;
+        let mut running = 0.0;
+        for lag in 1..=width {
+            let correlation = self.window_spectrum[lag].re as f64 * scale;
+            let shifted = self.energy[lag + width] - self.energy[lag];
+            let difference = (self.energy[width] + shifted - 2.0 * correlation).max(0.0);
+            running += difference;
+            self.difference[lag] = if running > 0.0 {
+                (difference * lag as f64 / running) as f32
+            } else {
+                1.0
+            };
+        }
+
+        // First dip under the threshold, followed down to its minimum.
+        let mut lag = (self.min_lag..width).find(|&lag| self.difference[lag] < self.threshold)?;
+        while lag + 1 < width && self.difference[lag + 1] < self.difference[lag] {
+            lag += 1;
+        }
+
+        let (before, at, after) = (
+            self.difference[lag - 1],
+            self.difference[lag],
+            self.difference[lag + 1],
+        );
+        let curvature = before - 2.0 * at + after;
+        let offset = if curvature > 0.0 {
+            (0.5 * (before - after) / curvature).clamp(-0.5, 0.5)
+        } else {
+            0.0
+        };
+
+        Some(Pitch {
+            frequency: self.sample_rate / (lag as f32 + offset),
+            clarity: (1.0 - at).clamp(0.0, 1.0),
+        })
+    }
+}
+
+/// Latest pitch from a [`PitchTracker`], readable from any thread without locking.
+#[derive(Debug, Default)]
+pub struct SharedPitch {
+    // f32 bits; zero frequency means no pitch.
+    frequency: AtomicU32,
+    clarity: AtomicU32,
+}
+
+impl SharedPitch {
+    pub fn get(&self) -> Option<Pitch> {
+        let frequency = f32::from_bits(self.frequency.load(Ordering::Relaxed));
+        (frequency > 0.0).then(|| Pitch {
+            frequency,
+            clarity: f32::from_bits(self.clarity.load(Ordering::Relaxed)),
+        })
+    }
+
+    fn set(&self, pitch: Option<Pitch>) {
+        let (frequency, clarity) = pitch.map_or((0.0, 0.0), |p| (p.frequency, p.clarity));
+        self.clarity.store(clarity.to_bits(), Ordering::Relaxed);
+        self.frequency.store(frequency.to_bits(), Ordering::Relaxed);
+    }
+}
+
+/// Pass-through effect that tracks the pitch of the audio running through it.
+///
+/// Channels are mixed to mono and analysed [`UPDATES_PER_SECOND`] times a
+/// second unless [`PitchTracker::with_update_rate`] says otherwise; the
+/// result is published through [`PitchTracker::shared`] for tuner displays
+/// and other effects. Put it first in a chain to follow the captured input.
+pub struct PitchTracker {
+    range: (f32, f32),
+    updates_per_second: u32,
+    sample_rate: u32,
+    channels: usize,
+    detector: PitchDetector,
+    history: Vec<f32>,
+    window: Vec<f32>,
+    position: usize,
+    hop: usize,
+    until_detect: usize,
+    shared: Arc<SharedPitch>,
+}
+
+impl PitchTracker {
+    pub fn new() -> Self {
+        Self::with_range(DEFAULT_RANGE.0, DEFAULT_RANGE.1)
+    }
+
+    /// Tracks fundamentals between `min_frequency` and `max_frequency` Hz.
+    pub fn with_range(min_frequency: f32, max_frequency: f32) -> Self {
+        let mut tracker = Self {
+            range: (min_frequency, max_frequency),
+            updates_per_second: UPDATES_PER_SECOND,
+            sample_rate: 0,
+            channels: 1,
+            detector: PitchDetector::new(DEFAULT_SAMPLE_RATE, min_frequency, max_frequency),
+            history: Vec::new(),
+            window: Vec::new(),
+            position: 0,
+            hop: 0,
+            until_detect: 0,
+            shared: Arc::default(),
+        };
+        tracker.prepare(DEFAULT_SAMPLE_RATE, 1);
+        tracker
+    }
+
+    /// Analyses the input `updates_per_second` times a second.
+    pub fn with_update_rate(mut self, updates_per_second: u32) -> Self {
+        self.updates_per_second = updates_per_second.max(1);
+        self.hop = (self.sample_rate / self.updates_per_second).max(1) as usize;
+        self.until_detect = self.hop;
+        self
+    }
+
+    /// Handle to the latest reading, for use on other threads.
+    pub fn shared(&self) -> Arc<SharedPitch> {
+        Arc::clone(&self.shared)
+    }
+
+    pub fn pitch(&self) -> Option<Pitch> {
+        self.shared.get()
+    }
+
+    fn detect(&mut self) {
+        // Unroll the circular history into time order.
+        let (older, newer) = self.history.split_at(self.position);
+        self.window[..newer.len()].copy_from_slice(newer);
+        self.window[newer.len()..].copy_from_slice(older);
+        let pitch = self.detector.detect(&self.window);
+        self.shared.set(pitch);
+    }
+}
+
+impl Default for PitchTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AudioProcessor for PitchTracker {
+    fn prepare(&mut self, sample_rate: u32, channels: usize) {
+        self.channels = channels;
+        if sample_rate != self.sample_rate {
+            self.sample_rate = sample_rate;
+            self.detector = PitchDetector::new(sample_rate, self.range.0, self.range.1);
+            self.history = vec![0.0; self.detector.window_len()];
+            self.window = vec![0.0; self.detector.window_len()];
+            self.hop = (sample_rate / self.updates_per_second).max(1) as usize;
+        }
+        self.reset();
+    }
+
+    fn process(&mut self, block: &mut [f32]) {
+        for frame in block.chunks_exact(self.channels) {
+            self.history[self.position] = frame.iter().sum::<f32>() / self.channels as f32;
+            self.position = (self.position + 1) % self.history.len();
+            self.until_detect -= 1;
+            if self.until_detect == 0 {
+                self.until_detect = self.hop;
+                self.detect();
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.history.fill(0.0);
+        self.position = 0;
+        self.until_detect = self.hop;
+        self.shared.set(None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    #[test]
+    fn finds_fundamental_of_harmonic_tone() {
+        let mut detector = PitchDetector::new(48_000, 60.0, 1000.0);
+        for &freq in &[82.4, 220.0, 659.3] {
+            let tone: Vec<f32> = (0..4096)
+                .map(|i| {
+                    let phase = 2.0 * PI * freq * i as f32 / 48_000.0;
+                    0.5 * phase.sin() + 0.3 * (2.0 * phase).sin() + 0.2 * (3.0 * phase).sin()
+                })
+                .collect();
+            let pitch = detector.detect(&tone).unwrap();
+            assert!((pitch.frequency - freq).abs() < freq * 0.002, "{freq} Hz");
+            assert!(pitch.clarity > 0.9);
+        }
+    }
+
+    #[test]
+    fn names_notes_and_cents() {
+        assert_eq!(Note::from_frequency(440.0), (Note(69), 0.0));
+        assert_eq!(Note(60).to_string(), "C4");
+        assert_eq!(Note(61).to_string(), "C#4");
+        assert_eq!(Note(23).to_string(), "B0");
+
+        let (note, cents) = Note::from_frequency(440.0 * 2.0_f32.powf(-0.2 / 12.0));
+        assert_eq!(note.to_string(), "A4");
+        assert!((cents + 20.0).abs() < 0.01);
+        assert!((Note(57).frequency() - 220.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn tracker_publishes_pitch_and_passes_audio_through() {
+        let mut tracker = PitchTracker::new();
+        tracker.prepare(48_000, 2);
+        let shared = tracker.shared();
+        assert_eq!(shared.get(), None);
+
+        let tone: Vec<f32> = (0..9600)
+            .flat_map(|i| {
+                let sample = 0.5 * (2.0 * PI * 196.0 * i as f32 / 48_000.0).sin();
+                [sample, sample]
+            })
+            .collect();
+        let mut block = tone.clone();
+        for chunk in block.chunks_mut(256) {
+            tracker.process(chunk);
+        }
+        assert_eq!(block, tone);
+
+        let pitch = shared.get().unwrap();
+        assert_eq!(pitch.note().to_string(), "G3");
+        assert!(pitch.cents().abs() < 1.0);
+    }
+
+    #[test]
+    fn rejects_silence_noise_and_short_input() {
+        let mut detector = PitchDetector::new(48_000, 60.0, 1000.0);
+        assert_eq!(detector.detect(&[0.0; 4096]), None);
+        assert_eq!(detector.detect(&[0.5; 16]), None);
+
+        let mut state = 0x1234_5678_u32;
+        let noise: Vec<f32> = (0..4096)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as f32 / u32::MAX as f32 - 0.5
+            })
+            .collect();
+        assert_eq!(detector.detect(&noise), None);
+    }
+}
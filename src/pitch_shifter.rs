@@ -0,0 +1,216 @@
+use crate::params::{Param, ParamInfo, Unit};
+use crate::processor::AudioProcessor;
+use crate::smoothing::{Ramp, SmoothedParam};
+use crate::vocoder::{Formants, PhaseVocoder};
+use std::time::Duration;
+
+/// STFT frame size used by the pitch shifter.
+pub const FRAME_SIZE: usize = 2048;
+/// Analysis frames overlapping each frame length.
+pub const OVERSAMPLING: usize = 4;
+/// Glide applied to pitch changes unless [`PitchShifter::with_ramp`] says otherwise.
+pub const DEFAULT_GLIDE: Ramp = Ramp::Exponential(Duration::from_millis(20));
+/// Samples processed between updates of the smoothed pitch.
+const SMOOTHING_BLOCK: usize = 32;
+/// Quefrency below which the cepstrum counts as spectral envelope rather
+/// than excitation; 2 ms keeps voices up to 500 Hz out of the envelope.
+const FORMANT_QUEFRENCY: f32 = 0.002;
+/// Rate assumed for glide times until [`AudioProcessor::prepare`] is called.
+const DEFAULT_SAMPLE_RATE: u32 = 48_000;
+
+/// Pitch factor read by the shifters, up to three octaves either way.
+pub const PITCH_FACTOR: ParamInfo =
+    ParamInfo::new("pitch", "Pitch", 0.125, 8.0, 1.0).with_unit(Unit::Ratio);
+/// Formant shift read by [`PitchShifter::with_formants`].
+pub const FORMANT_FACTOR: ParamInfo =
+    ParamInfo::new("formant", "Formants", 0.125, 8.0, 1.0).with_unit(Unit::Ratio);
+
+/// Algorithm used to shift pitch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Engine {
+    /// [`PitchShifter`]: phase vocoder, works on any material.
+    #[default]
+    Vocoder,
+    /// [`PsolaShifter`](crate::PsolaShifter): lower latency, monophonic voices only.
+    Psola,
+    /// [`WsolaShifter`](crate::WsolaShifter): waveform-similarity overlap-add,
+    /// also used for tempo changes.
+    Wsola,
+}
+
+/// Converts a shift in semitones to a pitch factor.
+pub fn semitones_to_ratio(semitones: f32) -> f32 {
+    2.0_f32.powf(semitones / 12.0)
+}
+
+/// Converts a pitch factor to a shift in semitones.
+pub fn ratio_to_semitones(ratio: f32) -> f32 {
+    12.0 * ratio.log2()
+}
+
+/// Duration-preserving pitch shifter driven by a shared pitch factor.
+///
+/// A factor of 2.0 raises the pitch by an octave, 0.5 lowers it by one,
+/// and 1.0 leaves it unchanged. Changes glide in semitones so that
+/// steps up and down sound equally smooth. Processes a single channel;
+/// wrap it in [`PerChannel`](crate::PerChannel) for multi-channel audio.
+///
+/// By default formants move with the pitch; [`PitchShifter::with_formants`]
+/// keeps them in place instead, which sounds far more natural on voices.
+pub struct PitchShifter {
+    vocoder: PhaseVocoder,
+    pitch_factor: Param,
+    semitones: SmoothedParam,
+    formant_factor: Option<Param>,
+    formant_semitones: SmoothedParam,
+    lifter: usize,
+}
+
+impl PitchShifter {
+    /// Creates a shifter that reads its factor from `pitch_factor` on every block.
+    pub fn new(pitch_factor: Param) -> Self {
+        let semitones = ratio_to_semitones(pitch_factor.get());
+        Self {
+            vocoder: PhaseVocoder::new(FRAME_SIZE, OVERSAMPLING),
+            pitch_factor,
+            semitones: SmoothedParam::new(semitones, DEFAULT_GLIDE, DEFAULT_SAMPLE_RATE),
+            formant_factor: None,
+            formant_semitones: SmoothedParam::new(0.0, DEFAULT_GLIDE, DEFAULT_SAMPLE_RATE),
+            lifter: lifter_for(DEFAULT_SAMPLE_RATE),
+        }
+    }
+
+    /// Sets how pitch and formant changes glide from one value to the next.
+    pub fn with_ramp(mut self, ramp: Ramp) -> Self {
+        self.semitones.set_ramp(ramp);
+        self.formant_semitones.set_ramp(ramp);
+        self
+    }
+
+    /// Preserves formants while shifting, then moves them independently by
+    /// the ratio read from `formant_factor` on every block.
+    pub fn with_formants(mut self, formant_factor: Param) -> Self {
+        let semitones = ratio_to_semitones(formant_factor.get());
+        self.formant_semitones.reset(semitones);
+        self.formant_factor = Some(formant_factor);
+        self
+    }
+
+    pub fn preserves_formants(&self) -> bool {
+        self.formant_factor.is_some()
+    }
+
+    /// Shifts `input` into `output` of the same length, leaving the input
+    /// untouched. Like [`AudioProcessor::process`] it never allocates.
+    pub fn process_into(&mut self, input: &[f32], output: &mut [f32]) {
+        assert_eq!(input.len(), output.len(), "input and output lengths differ");
+        output.copy_from_slice(input);
+        self.process(output);
+    }
+}
+
+impl AudioProcessor for PitchShifter {
+    fn prepare(&mut self, sample_rate: u32, channels: usize) {
+        assert_eq!(channels, 1, "PitchShifter processes a single channel");
+        self.semitones.set_sample_rate(sample_rate);
+        self.formant_semitones.set_sample_rate(sample_rate);
+        self.lifter = lifter_for(sample_rate);
+    }
+
+    fn process(&mut self, block: &mut [f32]) {
+        let target = ratio_to_semitones(self.pitch_factor.get());
+        if target != self.semitones.target() {
+            self.semitones.set_target(target);
+        }
+
+        if let Some(factor) = &self.formant_factor {
+            let target = ratio_to_semitones(factor.get());
+            if target != self.formant_semitones.target() {
+                self.formant_semitones.set_target(target);
+            }
+        }
+
+        for chunk in block.chunks_mut(SMOOTHING_BLOCK) {
+            let semitones = self.semitones.skip(chunk.len());
+            if self.formant_factor.is_some() {
+                let formant_semitones = self.formant_semitones.skip(chunk.len());
+                self.vocoder.set_formants(Some(Formants {
+                    lifter: self.lifter,
+                    shift: semitones_to_ratio(formant_semitones),
+                }));
+            }
+            self.vocoder
+                .process_in_place(chunk, semitones_to_ratio(semitones));
+        }
+    }
+
+    fn reset(&mut self) {
+        self.vocoder.reset();
+        let target = self.semitones.target();
+        self.semitones.reset(target);
+        let target = self.formant_semitones.target();
+        self.formant_semitones.reset(target);
+    }
+
+    fn latency(&self) -> usize {
+        self.vocoder.latency()
+    }
+}
+
+/// Cepstral lifter length for formant envelopes at `sample_rate`.
+fn lifter_for(sample_rate: u32) -> usize {
+    ((sample_rate as f32 * FORMANT_QUEFRENCY) as usize).clamp(1, FRAME_SIZE / 2 - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_semitones_to_ratio() {
+        assert!((semitones_to_ratio(12.0) - 2.0).abs() < 1e-6);
+        assert!((semitones_to_ratio(-12.0) - 0.5).abs() < 1e-6);
+        assert!((semitones_to_ratio(7.0) - 1.498_307).abs() < 1e-5);
+        assert!((ratio_to_semitones(semitones_to_ratio(-3.5)) + 3.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn follows_shared_pitch_factor() {
+        let pitch_factor = Param::new(PITCH_FACTOR);
+        let mut shifter = PitchShifter::new(pitch_factor.clone());
+        shifter.prepare(48_000, 1);
+        let input: Vec<f32> = (0..8192).map(|i| (i as f32 * 0.05).sin()).collect();
+
+        let mut unity = input.clone();
+        shifter.process(&mut unity);
+
+        shifter.reset();
+        pitch_factor.set(1.5);
+        let mut shifted = input.clone();
+        shifter.process(&mut shifted);
+
+        let latency = shifter.latency();
+        assert!((unity[6000] - input[6000 - latency]).abs() < 1e-3);
+        assert!(
+            unity[4096..]
+                .iter()
+                .zip(&shifted[4096..])
+                .any(|(a, b)| (a - b).abs() > 0.1)
+        );
+    }
+
+    #[test]
+    fn glides_to_new_pitch_factor() {
+        let pitch_factor = Param::new(PITCH_FACTOR);
+        let ramp = Ramp::Linear(Duration::from_millis(100));
+        let mut shifter = PitchShifter::new(pitch_factor.clone()).with_ramp(ramp);
+        shifter.prepare(1000, 1);
+        let mut block = [0.0; 50];
+
+        pitch_factor.set(2.0);
+        shifter.process(&mut block);
+        assert!((shifter.semitones.value() - 6.0).abs() < 1e-4);
+        shifter.process(&mut block);
+        assert_eq!(shifter.semitones.value(), 12.0);
+    }
+}
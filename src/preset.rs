@@ -0,0 +1,460 @@
+use crate::autotune::AutoTuneSettings;
+use crate::control::Controls;
+use crate::harmonizer::{HarmonizerSettings, Interval, MAX_VOICES, Voice};
+use crate::mix::LIMITER_NAME;
+use crate::pitch_detection::Note;
+use crate::scale::{self, Scale};
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::PoisonError;
+use std::time::Duration;
+
+/// Directory under the user's config directory that holds the presets.
+const CONFIG_DIR: &str = "audio_effects";
+const FILE_NAME: &str = "presets.toml";
+
+/// Snapshot of everything the control thread can change: parameter values,
+/// auto-tune and harmony settings, and the order and bypass state of the
+/// effects.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Preset {
+    /// Values by parameter id.
+    pub params: BTreeMap<String, f32>,
+    pub autotune: AutoTuneSettings,
+    pub harmony: HarmonizerSettings,
+    /// Effect names in chain order, with whether each is bypassed.
+    pub effects: Vec<(String, bool)>,
+}
+
+impl Preset {
+    pub fn capture(controls: &Controls) -> Self {
+        let effects = &controls.effects;
+        Self {
+            params: controls
+                .params
+                .iter()
+                .map(|param| (param.info().id.to_string(), param.get()))
+                .collect(),
+            autotune: *controls
+                .autotune
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner),
+            harmony: *controls
+                .harmony
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner),
+            effects: effects
+                .names()
+                .enumerate()
+                .map(|(index, name)| (name.to_string(), effects.is_bypassed(index)))
+                .collect(),
+        }
+    }
+
+    /// Sets `controls` to this snapshot.
+    ///
+    /// Parameters the preset doesn't mention go back to their defaults.
+    /// Effects it doesn't mention keep their bypass state and end up after
+    /// the ones it does. The limiter stays last and active whatever the
+    /// preset says. If the chain can't be rearranged, nothing changes.
+    pub fn apply(&self, controls: &mut Controls) -> Result<()> {
+        let effects = &mut controls.effects;
+        let limiter = effects.position(LIMITER_NAME);
+        let mut arrangement: Vec<(usize, bool)> = Vec::with_capacity(effects.len());
+        let listed = self
+            .effects
+            .iter()
+            .filter_map(|(name, bypassed)| Some((effects.position(name)?, *bypassed)));
+        let unlisted = (0..effects.len()).map(|index| (index, effects.is_bypassed(index)));
+        // Listed effects in the preset's order, then the rest as they are.
+        for (index, bypassed) in listed.chain(unlisted) {
+            if Some(index) != limiter && arrangement.iter().all(|&(other, _)| other != index) {
+                arrangement.push((index, bypassed));
+            }
+        }
+        if let Some(index) = limiter {
+            arrangement.push((index, false));
+        }
+        effects.arrange(&arrangement)?;
+
+        for param in controls.params.iter() {
+            match self.params.get(param.info().id) {
+                Some(&value) => {
+                    param.set(value);
+                }
+                None => param.reset(),
+            }
+        }
+        *controls
+            .autotune
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = self.autotune;
+        *controls
+            .harmony
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = self.harmony;
+        Ok(())
+    }
+}
+
+/// Named presets kept together in one TOML file.
+pub struct PresetStore {
+    path: PathBuf,
+}
+
+impl PresetStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// `presets.toml` under the user's config directory, e.g.
+    /// `~/.config/audio_effects` or `$XDG_CONFIG_HOME/audio_effects`.
+    pub fn open_default() -> Result<Self> {
+        let dir = dirs::config_dir().context("No config directory to keep presets in")?;
+        Ok(Self::new(dir.join(CONFIG_DIR).join(FILE_NAME)))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Names of the saved presets in alphabetical order.
+    pub fn names(&self) -> Result<Vec<String>> {
+        Ok(self.read()?.into_keys().collect())
+    }
+
+    pub fn load(&self, name: &str) -> Result<Preset> {
+        let file = self
+            .read()?
+            .remove(name)
+            .with_context(|| format!("No preset named {name}"))?;
+        file.into_preset()
+            .with_context(|| format!("Preset {name} is invalid"))
+    }
+
+    /// Saves `preset` as `name`, replacing any preset of that name.
+    pub fn save(&self, name: &str, preset: &Preset) -> Result<()> {
+        if name.trim().is_empty() {
+            bail!("Preset name is empty");
+        }
+        let mut presets = self.read()?;
+        presets.insert(name.to_string(), PresetFile::from(preset));
+        self.write(&presets)
+    }
+
+    pub fn delete(&self, name: &str) -> Result<()> {
+        let mut presets = self.read()?;
+        if presets.remove(name).is_none() {
+            bail!("No preset named {name}");
+        }
+        self.write(&presets)
+    }
+
+    fn read(&self) -> Result<BTreeMap<String, PresetFile>> {
+        match fs::read_to_string(&self.path) {
+            Ok(text) => toml::from_str(&text)
+                .with_context(|| format!("Could not parse {}", self.path.display())),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+            Err(err) => Err(err).with_context(|| format!("Could not read {}", self.path.display())),
+        }
+    }
+
+    /// Replaces the file in one step, so a failed write leaves the old
+    /// presets in place.
+    fn write(&self, presets: &BTreeMap<String, PresetFile>) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("Could not create {}", dir.display()))?;
+        }
+        let temporary = self.path.with_extension("toml.tmp");
+        fs::write(&temporary, toml::to_string(presets)?)
+            .with_context(|| format!("Could not write {}", temporary.display()))?;
+        fs::rename(&temporary, &self.path)
+            .with_context(|| format!("Could not write {}", self.path.display()))
+    }
+}
+
+// On-disk layout, kept in plain strings and numbers so the file stays easy
+// to edit by hand.
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+struct PresetFile {
+    params: BTreeMap<String, f32>,
+    autotune: AutoTuneFile,
+    harmony: HarmonyFile,
+    effects: Vec<EffectFile>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+struct AutoTuneFile {
+    enabled: bool,
+    scale: Vec<String>,
+    retune_ms: u64,
+    humanize: f32,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+struct HarmonyFile {
+    key: Vec<String>,
+    dry_level: f32,
+    voices: Vec<VoiceFile>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct VoiceFile {
+    /// Position at the prompt, from 1.
+    number: usize,
+    /// `+4`, `-3.5` semitones or `+2d` scale steps.
+    interval: String,
+    level: f32,
+    pan: f32,
+    delay_ms: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct EffectFile {
+    name: String,
+    #[serde(default)]
+    bypassed: bool,
+}
+
+impl Default for PresetFile {
+    fn default() -> Self {
+        Self::from(&Preset {
+            params: BTreeMap::new(),
+            autotune: AutoTuneSettings::default(),
+            harmony: HarmonizerSettings::default(),
+            effects: Vec::new(),
+        })
+    }
+}
+
+impl Default for AutoTuneFile {
+    fn default() -> Self {
+        PresetFile::default().autotune
+    }
+}
+
+impl Default for HarmonyFile {
+    fn default() -> Self {
+        PresetFile::default().harmony
+    }
+}
+
+impl From<&Preset> for PresetFile {
+    fn from(preset: &Preset) -> Self {
+        let harmony = &preset.harmony;
+        Self {
+            params: preset.params.clone(),
+            autotune: AutoTuneFile {
+                enabled: preset.autotune.enabled,
+                scale: scale_names(&preset.autotune.scale),
+                retune_ms: preset.autotune.retune_speed.as_millis() as u64,
+                humanize: preset.autotune.humanize,
+            },
+            harmony: HarmonyFile {
+                key: scale_names(&harmony.scale),
+                dry_level: harmony.dry_level,
+                voices: harmony
+                    .voices
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(index, voice)| {
+                        let voice = voice.as_ref()?;
+                        Some(VoiceFile {
+                            number: index + 1,
+                            interval: match voice.interval {
+                                Interval::Semitones(semitones) => format!("{semitones:+}"),
+                                Interval::Diatonic(steps) => format!("{steps:+}d"),
+                            },
+                            level: voice.level,
+                            pan: voice.pan,
+                            delay_ms: voice.delay.as_millis() as u64,
+                        })
+                    })
+                    .collect(),
+            },
+            effects: preset
+                .effects
+                .iter()
+                .map(|(name, bypassed)| EffectFile {
+                    name: name.clone(),
+                    bypassed: *bypassed,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl PresetFile {
+    fn into_preset(self) -> Result<Preset> {
+        let mut harmony = HarmonizerSettings {
+            scale: parse_scale(&self.harmony.key)?,
+            ..HarmonizerSettings::default()
+        };
+        harmony.set_dry_level(self.harmony.dry_level)?;
+        for voice in &self.harmony.voices {
+            let slot = match voice.number {
+                number @ 1..=MAX_VOICES => &mut harmony.voices[number - 1],
+                _ => bail!("Voices are numbered 1 to {MAX_VOICES}"),
+            };
+            // Through the prompt syntax, so the same limits apply.
+            let text = format!(
+                "{} {} {} {}",
+                voice.interval, voice.level, voice.pan, voice.delay_ms
+            );
+            *slot = Some(text.parse::<Voice>()?);
+        }
+        Ok(Preset {
+            params: self.params,
+            autotune: AutoTuneSettings {
+                enabled: self.autotune.enabled,
+                scale: parse_scale(&self.autotune.scale)?,
+                retune_speed: Duration::from_millis(self.autotune.retune_ms),
+                humanize: self.autotune.humanize.clamp(0.0, 1.0),
+            },
+            harmony,
+            effects: self
+                .effects
+                .into_iter()
+                .map(|effect| (effect.name, effect.bypassed))
+                .collect(),
+        })
+    }
+}
+
+fn scale_names(scale: &Scale) -> Vec<String> {
+    (0..12)
+        .filter(|&pitch_class| scale.contains(pitch_class))
+        .map(|pitch_class| Note::NAMES[pitch_class].to_string())
+        .collect()
+}
+
+fn parse_scale(names: &[String]) -> Result<Scale> {
+    let mut scale = Scale::CHROMATIC;
+    for pitch_class in 0..12 {
+        scale.set_note(pitch_class, false);
+    }
+    for name in names {
+        scale.set_note(scale::parse_pitch_class(name)?, true);
+    }
+    Ok(scale)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EffectChain;
+    use crate::params::{ParamInfo, ParamRegistry};
+    use crate::processor::AudioProcessor;
+    use std::sync::{Arc, Mutex};
+
+    struct Silence;
+
+    impl AudioProcessor for Silence {
+        fn prepare(&mut self, _sample_rate: u32, _channels: usize) {}
+
+        fn process(&mut self, block: &mut [f32]) {
+            block.fill(0.0);
+        }
+
+        fn reset(&mut self) {}
+    }
+
+    /// Controls for a three-effect chain, returned with the chain so its
+    /// remote keeps working.
+    fn test_controls() -> (Controls, EffectChain) {
+        let mut params = ParamRegistry::new();
+        params.add(ParamInfo::new("pitch", "Pitch", 0.5, 2.0, 1.0));
+        params.add(ParamInfo::new("mix", "Mix", 0.0, 1.0, 1.0));
+        let mut chain = EffectChain::new();
+        for name in ["tuner", "pitch", "harmony", LIMITER_NAME] {
+            chain.push(name, Silence);
+        }
+        let controls = Controls {
+            params,
+            autotune: Arc::default(),
+            harmony: Arc::new(Mutex::new(HarmonizerSettings::default())),
+            effects: chain.remote(),
+        };
+        (controls, chain)
+    }
+
+    #[test]
+    fn saved_presets_restore_controls() {
+        let dir = std::env::temp_dir().join(format!("presets-{}", std::process::id()));
+        let store = PresetStore::new(dir.join("presets.toml"));
+        assert!(store.names().unwrap().is_empty());
+
+        let (mut controls, _chain) = test_controls();
+        controls.params.get("pitch").unwrap().set(1.5);
+        {
+            let mut autotune = controls.autotune.lock().unwrap();
+            autotune.scale = "E".parse::<Scale>().unwrap();
+            autotune.scale.set_note(4, false);
+            autotune.scale.set_note(1, true);
+        }
+        controls.harmony.lock().unwrap().voices[2] = Some("+2d 0.5 -1 30".parse().unwrap());
+        controls.effects.move_effect(2, 0).unwrap();
+        controls.effects.set_bypassed(1, true).unwrap();
+        let saved = Preset::capture(&controls);
+        store.save("duet", &saved).unwrap();
+        store.save("other", &saved).unwrap();
+
+        let (mut fresh, _fresh_chain) = test_controls();
+        fresh.params.get("mix").unwrap().set(0.5);
+        store.load("duet").unwrap().apply(&mut fresh).unwrap();
+        assert_eq!(Preset::capture(&fresh), saved);
+        assert_eq!(fresh.params.get("mix").unwrap().get(), 1.0);
+        let names: Vec<_> = fresh.effects.names().collect();
+        assert_eq!(names, ["harmony", "tuner", "pitch", "limiter"]);
+
+        assert_eq!(store.names().unwrap(), ["duet", "other"]);
+        store.delete("duet").unwrap();
+        assert!(store.load("duet").is_err());
+        assert!(store.delete("duet").is_err());
+        assert_eq!(store.names().unwrap(), ["other"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn limiter_stays_last_and_active() {
+        let (mut controls, _chain) = test_controls();
+        let mut preset = Preset::capture(&controls);
+        preset.effects = vec![
+            ("limiter".to_string(), true),
+            ("harmony".to_string(), false),
+            ("pitch".to_string(), false),
+        ];
+        preset.apply(&mut controls).unwrap();
+        let names: Vec<_> = controls.effects.names().collect();
+        assert_eq!(names, ["harmony", "pitch", "tuner", "limiter"]);
+        assert!(!controls.effects.is_bypassed(3));
+    }
+
+    #[test]
+    fn rejects_bad_dry_levels_and_partial_changes() {
+        let mut file = PresetFile::default();
+        file.harmony.dry_level = f32::NAN;
+        assert!(file.into_preset().is_err());
+
+        // With the chain's edit queue full, applying fails and leaves every
+        // control as it was.
+        let (mut controls, _chain) = test_controls();
+        let mut preset = Preset::capture(&controls);
+        preset.params.insert("mix".to_string(), 0.25);
+        preset.effects.reverse();
+        while controls.effects.set_bypassed(0, false).is_ok() {}
+        assert!(preset.apply(&mut controls).is_err());
+        assert_eq!(controls.params.get("mix").unwrap().get(), 1.0);
+        let names: Vec<_> = controls.effects.names().collect();
+        assert_eq!(names, ["tuner", "pitch", "harmony", "limiter"]);
+    }
+}
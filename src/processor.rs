@@ -0,0 +1,68 @@
+use std::sync::{Mutex, TryLockError};
+
+/// Largest block, in frames, that the streams pass to
+/// [`AudioProcessor::process`]; longer device callbacks are split up.
+/// Processors that need scratch space can size it for this in `prepare`.
+pub const MAX_BLOCK_FRAMES: usize = 1024;
+
+/// A block-based audio effect that runs on the output callback.
+pub trait AudioProcessor: Send {
+    /// Configures the processor for interleaved blocks of `channels`
+    /// channels at `sample_rate`. Called before the first block and again
+    /// whenever the stream format changes; this is where buffers get
+    /// allocated, since `process` runs on the audio thread and must not.
+    fn prepare(&mut self, sample_rate: u32, channels: usize);
+
+    /// Processes interleaved `block` in place.
+    fn process(&mut self, block: &mut [f32]);
+
+    /// Clears internal state such as delay lines and phase history.
+    fn reset(&mut self);
+
+    /// Delay in frames between an input frame and its processed output.
+    fn latency(&self) -> usize {
+        0
+    }
+}
+
+impl<P: AudioProcessor + ?Sized> AudioProcessor for Box<P> {
+    fn prepare(&mut self, sample_rate: u32, channels: usize) {
+        (**self).prepare(sample_rate, channels);
+    }
+
+    fn process(&mut self, block: &mut [f32]) {
+        (**self).process(block);
+    }
+
+    fn reset(&mut self) {
+        (**self).reset();
+    }
+
+    fn latency(&self) -> usize {
+        (**self).latency()
+    }
+}
+
+/// Reads a control value shared with another thread, for use inside
+/// [`AudioProcessor::process`].
+///
+/// Never blocks or panics: returns `None` if the other thread holds the
+/// lock right now, in which case the caller keeps its previous value, and
+/// reads through a poisoned lock.
+pub fn try_read<T: Copy>(shared: &Mutex<T>) -> Option<T> {
+    match shared.try_lock() {
+        Ok(value) => Some(*value),
+        Err(TryLockError::Poisoned(poisoned)) => Some(*poisoned.into_inner()),
+        Err(TryLockError::WouldBlock) => None,
+    }
+}
+
+/// Writes a control value from the audio thread under the same rules as
+/// [`try_read`]; the write is skipped if the lock is busy.
+pub fn try_write<T>(shared: &Mutex<T>, value: T) {
+    match shared.try_lock() {
+        Ok(mut guard) => *guard = value,
+        Err(TryLockError::Poisoned(poisoned)) => *poisoned.into_inner() = value,
+        Err(TryLockError::WouldBlock) => {}
+    }
+}
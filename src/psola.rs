@@ -0,0 +1,315 @@
+use crate::params::Param;
+use crate::pitch_detection::PitchDetector;
+use crate::pitch_shifter::{DEFAULT_GLIDE, ratio_to_semitones, semitones_to_ratio};
+use crate::processor::AudioProcessor;
+use crate::smoothing::{Ramp, SmoothedParam};
+use std::collections::VecDeque;
+use std::f32::consts::PI;
+
+/// Lowest fundamental tracked unless [`PsolaShifter::with_min_frequency`] says otherwise.
+pub const DEFAULT_MIN_FREQUENCY: f32 = 100.0;
+/// Highest fundamental tracked.
+const MAX_FREQUENCY: f32 = 1000.0;
+/// Grain spacing, in Hz, used while the input is unvoiced.
+const UNVOICED_FREQUENCY: f32 = 200.0;
+/// Samples between pitch detections.
+const DETECT_HOP: usize = 256;
+/// Samples between placing new epoch marks and grains.
+const GRAIN_HOP: usize = 32;
+/// Rate assumed until [`AudioProcessor::prepare`] is called.
+const DEFAULT_SAMPLE_RATE: u32 = 48_000;
+
+/// Time-domain pitch-synchronous overlap-add (TD-PSOLA) pitch shifter.
+///
+/// A [`PitchDetector`] tracks the input period and places an epoch mark on
+/// each cycle's peak. Two-period Hann grains cut around those marks are
+/// laid down at the period divided by the pitch factor, so pitch changes
+/// while duration and formants stay put. Latency is about two periods of
+/// the lowest tracked frequency, under half a vocoder frame, but it only
+/// suits monophonic sources such as a single voice. Unvoiced input passes
+/// through unshifted.
+pub struct PsolaShifter {
+    pitch_factor: Param,
+    semitones: SmoothedParam,
+    min_frequency: f32,
+    sample_rate: u32,
+    detector: PitchDetector,
+    max_period: usize,
+    unvoiced_period: f32,
+    // Input and overlap-added output, indexed by absolute sample position.
+    history: Vec<f32>,
+    output: Vec<f32>,
+    mask: usize,
+    written: usize,
+    // Detection runs on a contiguous copy of the newest input.
+    detect_window: Vec<f32>,
+    period: f32,
+    voiced: bool,
+    // Epoch marks (position, period) that grains may still be cut around.
+    marks: VecDeque<(usize, f32)>,
+    next_grain: f64,
+}
+
+impl PsolaShifter {
+    /// Creates a shifter that reads its factor from `pitch_factor` on every block.
+    pub fn new(pitch_factor: Param) -> Self {
+        let semitones = ratio_to_semitones(pitch_factor.get());
+        let mut shifter = Self {
+            pitch_factor,
+            semitones: SmoothedParam::new(semitones, DEFAULT_GLIDE, DEFAULT_SAMPLE_RATE),
+            min_frequency: DEFAULT_MIN_FREQUENCY,
+            sample_rate: 0,
+            detector: PitchDetector::new(DEFAULT_SAMPLE_RATE, DEFAULT_MIN_FREQUENCY, MAX_FREQUENCY),
+            max_period: 0,
+            unvoiced_period: 0.0,
+            history: Vec::new(),
+            output: Vec::new(),
+            mask: 0,
+            written: 0,
+            detect_window: Vec::new(),
+            period: 0.0,
+            voiced: false,
+            marks: VecDeque::new(),
+            next_grain: 0.0,
+        };
+        shifter.allocate(DEFAULT_SAMPLE_RATE);
+        shifter
+    }
+
+    /// Sets how pitch changes glide from one value to the next.
+    pub fn with_ramp(mut self, ramp: Ramp) -> Self {
+        self.semitones.set_ramp(ramp);
+        self
+    }
+
+    /// Lowers or raises the lowest trackable fundamental; higher values
+    /// reduce latency.
+    pub fn with_min_frequency(mut self, min_frequency: f32) -> Self {
+        assert!(
+            0.0 < min_frequency && min_frequency < MAX_FREQUENCY,
+            "minimum frequency must be between 0 and {MAX_FREQUENCY} Hz"
+        );
+        self.min_frequency = min_frequency;
+        self.allocate(self.sample_rate);
+        self
+    }
+
+    /// Sizes every buffer for `sample_rate` and clears the state.
+    fn allocate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+        self.detector = PitchDetector::new(sample_rate, self.min_frequency, MAX_FREQUENCY);
+        self.max_period = (sample_rate as f32 / self.min_frequency).ceil() as usize;
+        self.unvoiced_period = sample_rate as f32 / UNVOICED_FREQUENCY;
+
+        let len = (8 * self.max_period)
+            .max(2 * self.detector.window_len())
+            .next_power_of_two();
+        self.history = vec![0.0; len];
+        self.output = vec![0.0; len];
+        self.mask = len - 1;
+        self.detect_window = vec![0.0; self.detector.window_len()];
+        self.marks = VecDeque::with_capacity(len / 2);
+        self.reset();
+    }
+
+    /// Reads the newest input sample at absolute position `position`.
+    fn input(&self, position: usize) -> f32 {
+        self.history[position & self.mask]
+    }
+
+    fn detect(&mut self) {
+        let len = self.detect_window.len();
+        if self.written < len {
+            return;
+        }
+        for (i, sample) in self.detect_window.iter_mut().enumerate() {
+            *sample = self.history[(self.written - len + i) & self.mask];
+        }
+        match self.detector.detect(&self.detect_window) {
+            Some(pitch) => {
+                self.period = pitch.period(self.sample_rate);
+                self.voiced = true;
+            }
+            None => {
+                self.period = self.unvoiced_period;
+                self.voiced = false;
+            }
+        }
+    }
+
+    /// Places epoch marks for every cycle whose search window has arrived.
+    fn mark_epochs(&mut self) {
+        loop {
+            let last = self.marks.back().map_or(0, |&(mark, _)| mark);
+            let period = self.period;
+            let candidate = last + period.round() as usize;
+            let reach = (period / 4.0) as usize;
+            if candidate + reach >= self.written {
+                break;
+            }
+
+            // Lock voiced marks onto the cycle's peak so grains line up.
+            let mark = if self.voiced {
+                (candidate - reach..=candidate + reach)
+                    .max_by(|&a, &b| self.input(a).total_cmp(&self.input(b)))
+                    .unwrap()
+            } else {
+                candidate
+            };
+            self.marks.push_back((mark, period));
+        }
+    }
+
+    /// How far behind the newest input a grain's centre must be before its
+    /// mark and the input it cuts are both known.
+    fn lookahead(&self) -> usize {
+        // A grain reads up to a period past its mark, which is at or before
+        // the centre. Marks still to be placed land at most half a period
+        // before the newest input, so they can't precede the centre either.
+        (self.max_period as f32).max(self.unvoiced_period).ceil() as usize
+    }
+
+    /// Overlap-adds grains while the mark each one needs is known.
+    fn place_grains(&mut self, pitch_factor: f32) {
+        let emitted = self.written.saturating_sub(self.latency());
+        let lookahead = self.lookahead();
+        while !self.marks.is_empty() {
+            let time = self.next_grain.round() as usize;
+            // A later mark could still land at or before `time`.
+            if time + lookahead >= self.written {
+                break;
+            }
+            // Grain from the latest mark at or before `time`.
+            let Some(&(mark, period)) = self.marks.iter().rev().find(|&&(mark, _)| mark <= time)
+            else {
+                self.next_grain += self.unvoiced_period as f64;
+                continue;
+            };
+
+            let half = (period.round() as usize).min(self.max_period);
+            for offset in 0..2 * half {
+                let source = (mark + offset).checked_sub(half);
+                let target = (time + offset).checked_sub(half);
+                if let (Some(source), Some(target)) = (source, target)
+                    && target >= emitted
+                {
+                    let window = 0.5 - 0.5 * (PI * offset as f32 / half as f32).cos();
+                    self.output[target & self.mask] += self.input(source) * window;
+                }
+            }
+
+            let step = if self.voiced {
+                period / pitch_factor
+            } else {
+                period
+            };
+            self.next_grain += step as f64;
+
+            // Marks more than a grain behind the next one are no longer needed.
+            let oldest_needed = (self.next_grain as usize).saturating_sub(2 * self.max_period);
+            while self.marks.len() > 1 && self.marks[1].0 <= oldest_needed {
+                self.marks.pop_front();
+            }
+        }
+    }
+}
+
+impl AudioProcessor for PsolaShifter {
+    fn prepare(&mut self, sample_rate: u32, channels: usize) {
+        assert_eq!(channels, 1, "PsolaShifter processes a single channel");
+        self.semitones.set_sample_rate(sample_rate);
+        if sample_rate != self.sample_rate {
+            self.allocate(sample_rate);
+        }
+    }
+
+    fn process(&mut self, block: &mut [f32]) {
+        let target = ratio_to_semitones(self.pitch_factor.get());
+        if target != self.semitones.target() {
+            self.semitones.set_target(target);
+        }
+
+        let latency = self.latency();
+        for sample in block.iter_mut() {
+            self.history[self.written & self.mask] = *sample;
+            self.written += 1;
+
+            if self.written.is_multiple_of(GRAIN_HOP) {
+                if self.written.is_multiple_of(DETECT_HOP) {
+                    self.detect();
+                }
+                let pitch_factor = semitones_to_ratio(self.semitones.skip(GRAIN_HOP));
+                self.mark_epochs();
+                self.place_grains(pitch_factor);
+            }
+
+            *sample = match self.written.checked_sub(latency) {
+                Some(position) => std::mem::take(&mut self.output[position & self.mask]),
+                None => 0.0,
+            };
+        }
+    }
+
+    fn reset(&mut self) {
+        self.history.fill(0.0);
+        self.output.fill(0.0);
+        self.written = 0;
+        self.period = self.unvoiced_period;
+        self.voiced = false;
+        self.marks.clear();
+        self.next_grain = 0.0;
+        let target = self.semitones.target();
+        self.semitones.reset(target);
+    }
+
+    fn latency(&self) -> usize {
+        // A grain reaches back up to a period from its centre, and grains
+        // are only placed every GRAIN_HOP samples.
+        self.max_period + self.lookahead() + GRAIN_HOP
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pitch_shifter::{FRAME_SIZE, OVERSAMPLING, PITCH_FACTOR};
+    use crate::vocoder::PhaseVocoder;
+
+    fn voice(freq: f32, len: usize) -> Vec<f32> {
+        (0..len)
+            .map(|i| {
+                let phase = 2.0 * PI * freq * i as f32 / 48_000.0;
+                0.4 * phase.sin() + 0.2 * (2.0 * phase).sin() + 0.1 * (3.0 * phase).sin()
+            })
+            .collect()
+    }
+
+    fn fundamental(samples: &[f32]) -> f32 {
+        let mut detector = PitchDetector::new(48_000, 60.0, 1000.0);
+        detector.detect(samples).unwrap().frequency
+    }
+
+    #[test]
+    fn shifts_voiced_input_with_less_latency_than_the_vocoder() {
+        let vocoder_latency = PhaseVocoder::new(FRAME_SIZE, OVERSAMPLING).latency();
+        let input = voice(200.0, 48_000);
+        for &(factor, expected) in &[(1.0, 200.0), (1.25, 250.0), (0.8, 160.0)] {
+            let mut shifter = PsolaShifter::new(Param::new(PITCH_FACTOR.with_default(factor)));
+            shifter.prepare(48_000, 1);
+            assert!(shifter.latency() < vocoder_latency / 2);
+
+            let mut output = input.clone();
+            for block in output.chunks_mut(480) {
+                shifter.process(block);
+            }
+            let tail = &output[24_000..];
+            assert!(
+                (fundamental(tail) - expected).abs() < 2.0,
+                "factor {factor}"
+            );
+
+            let rms = |s: &[f32]| (s.iter().map(|x| x * x).sum::<f32>() / s.len() as f32).sqrt();
+            assert!((rms(tail) / rms(&input[24_000..]) - 1.0).abs() < 0.25);
+        }
+    }
+}
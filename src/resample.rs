@@ -0,0 +1,221 @@
+use std::f64::consts::PI;
+
+/// Filter phases stored per input sample; intermediate phases are interpolated.
+const PHASES: usize = 256;
+/// Zero crossings of the sinc kernel on each side of its centre.
+const ZERO_CROSSINGS: usize = 16;
+/// Cutoff as a fraction of the lower Nyquist frequency, leaving room for the transition band.
+const ROLLOFF: f64 = 0.95;
+
+/// Streaming band-limited resampler for interleaved audio.
+///
+/// Uses a Blackman-windowed sinc filter stored as a polyphase table. When
+/// downsampling the cutoff follows the output Nyquist frequency so nothing
+/// aliases.
+pub struct Resampler {
+    channels: usize,
+    // Input frames advanced per output frame, as the reduced ratio step_num / step_den.
+    step_num: u64,
+    step_den: u64,
+    half_width: usize,
+    table: Vec<f32>,
+    history: Vec<f32>,
+    // Read position relative to the start of `history`: index + frac / step_den frames.
+    // Kept exact so chunked and one-shot processing produce identical output.
+    index: usize,
+    frac: u64,
+}
+
+impl Resampler {
+    pub fn new(input_rate: u32, output_rate: u32, channels: usize) -> Self {
+        assert!(
+            input_rate > 0 && output_rate > 0,
+            "sample rates must be non-zero"
+        );
+        assert!(channels > 0, "need at least one channel");
+
+        let divisor = gcd(input_rate as u64, output_rate as u64);
+        let step_num = input_rate as u64 / divisor;
+        let step_den = output_rate as u64 / divisor;
+        let cutoff = (step_den as f64 / step_num as f64).min(1.0) * ROLLOFF;
+        let half_width = (ZERO_CROSSINGS as f64 / cutoff).ceil() as usize;
+        let taps = 2 * half_width;
+
+        let mut table = Vec::with_capacity((PHASES + 1) * taps);
+        for phase in 0..=PHASES {
+            let frac = phase as f64 / PHASES as f64;
+            for tap in 0..taps {
+                let t = frac + (half_width - 1) as f64 - tap as f64;
+                table.push(kernel(t, cutoff, half_width as f64) as f32);
+            }
+        }
+
+        let mut resampler = Self {
+            channels,
+            step_num,
+            step_den,
+            half_width,
+            table,
+            history: Vec::new(),
+            index: 0,
+            frac: 0,
+        };
+        resampler.reset();
+        resampler
+    }
+
+    /// Delay in input frames before a sample can be resampled.
+    pub fn latency(&self) -> usize {
+        self.half_width
+    }
+
+    /// Most output frames a single [`Resampler::process`] call can produce
+    /// from `input_frames` frames.
+    pub fn max_output_frames(&self, input_frames: usize) -> usize {
+        (input_frames as u64 * self.step_den).div_ceil(self.step_num) as usize + 1
+    }
+
+    /// Makes room for blocks of up to `input_frames` frames, so processing
+    /// them doesn't allocate.
+    pub fn reserve(&mut self, input_frames: usize) {
+        let needed = (2 * self.half_width + input_frames) * self.channels;
+        self.history
+            .reserve(needed.saturating_sub(self.history.len()));
+    }
+
+    /// Clears buffered input.
+    pub fn reset(&mut self) {
+        // Leading silence lets the first input frame sit at the filter centre.
+        self.history.clear();
+        self.history.resize(self.half_width * self.channels, 0.0);
+        self.index = self.half_width;
+        self.frac = 0;
+    }
+
+    /// Resamples interleaved `input` and appends every output frame that can
+    /// be computed so far to `output`.
+    pub fn process(&mut self, input: &[f32], output: &mut Vec<f32>) {
+        let channels = self.channels;
+        let taps = 2 * self.half_width;
+        self.history.extend_from_slice(input);
+        let frames = self.history.len() / channels;
+
+        while self.index + self.half_width < frames {
+            let phase_position = self.frac as f64 / self.step_den as f64 * PHASES as f64;
+            let phase = phase_position.floor() as usize;
+            let t = (phase_position - phase as f64) as f32;
+            let row0 = &self.table[phase * taps..(phase + 1) * taps];
+            let row1 = &self.table[(phase + 1) * taps..(phase + 2) * taps];
+            let first = self.index + 1 - self.half_width;
+
+            for channel in 0..channels {
+                let mut acc = 0.0;
+                for (tap, (c0, c1)) in row0.iter().zip(row1).enumerate() {
+                    let coefficient = c0 + t * (c1 - c0);
+                    acc += coefficient * self.history[(first + tap) * channels + channel];
+                }
+                output.push(acc);
+            }
+
+            self.frac += self.step_num;
+            self.index += (self.frac / self.step_den) as usize;
+            self.frac %= self.step_den;
+        }
+
+        // Drop frames that no future output can reach.
+        let discard = (self.index + 1).saturating_sub(self.half_width).min(frames);
+        self.history.drain(..discard * channels);
+        self.index -= discard;
+    }
+}
+
+/// Resamples a complete interleaved signal, returning the aligned result.
+pub fn resample(samples: &[f32], channels: usize, input_rate: u32, output_rate: u32) -> Vec<f32> {
+    if input_rate == output_rate {
+        return samples.to_vec();
+    }
+
+    let mut resampler = Resampler::new(input_rate, output_rate, channels);
+    let mut output = Vec::new();
+    resampler.process(samples, &mut output);
+    // Flush the filter's lookahead with silence.
+    resampler.process(&vec![0.0; resampler.latency() * channels], &mut output);
+
+    let frames = samples.len() / channels;
+    let expected = (frames as u64 * output_rate as u64).div_ceil(input_rate as u64) as usize;
+    output.truncate(expected * channels);
+    output
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
+fn kernel(t: f64, cutoff: f64, half_width: f64) -> f64 {
+    if t.abs() >= half_width {
+        return 0.0;
+    }
+    let x = PI * cutoff * t;
+    let sinc = if x == 0.0 { 1.0 } else { x.sin() / x };
+    let w = t / half_width;
+    let blackman = 0.42 + 0.5 * (PI * w).cos() + 0.08 * (2.0 * PI * w).cos();
+    cutoff * sinc * blackman
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(freq: f32, rate: u32, len: usize) -> Vec<f32> {
+        (0..len)
+            .map(|i| 0.5 * (2.0 * std::f32::consts::PI * freq * i as f32 / rate as f32).sin())
+            .collect()
+    }
+
+    fn rms(samples: &[f32]) -> f32 {
+        (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn preserves_pitch_and_level_when_upsampling() {
+        let input = sine(1000.0, 44_100, 44_100);
+        let output = resample(&input, 1, 44_100, 48_000);
+        assert_eq!(output.len(), 48_000);
+
+        let expected = sine(1000.0, 48_000, 48_000);
+        for i in 1000..47_000 {
+            assert!((output[i] - expected[i]).abs() < 1e-3, "sample {i}");
+        }
+    }
+
+    #[test]
+    fn removes_content_above_output_nyquist() {
+        let input = sine(10_000.0, 48_000, 48_000);
+        let output = resample(&input, 1, 48_000, 16_000);
+        assert_eq!(output.len(), 16_000);
+        assert!(rms(&output[1000..15_000]) < 1e-3);
+    }
+
+    #[test]
+    fn streaming_matches_one_shot_per_channel() {
+        let left = sine(440.0, 48_000, 4800);
+        let right = sine(880.0, 48_000, 4800);
+        let interleaved: Vec<f32> = left
+            .iter()
+            .zip(&right)
+            .flat_map(|(l, r)| [*l, *r])
+            .collect();
+
+        let mut resampler = Resampler::new(48_000, 44_100, 2);
+        let mut streamed = Vec::new();
+        for chunk in interleaved.chunks(2 * 97) {
+            resampler.process(chunk, &mut streamed);
+        }
+
+        let one_shot = resample(&interleaved, 2, 48_000, 44_100);
+        assert_eq!(streamed[..], one_shot[..streamed.len()]);
+
+        let left_only: Vec<f32> = one_shot.iter().step_by(2).copied().collect();
+        assert_eq!(left_only, resample(&left, 1, 48_000, 44_100));
+    }
+}
@@ -0,0 +1,437 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+/// How the consumer reacts when the fill level drifts away from the latency
+/// target because the input and output clocks run at slightly different rates.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DriftPolicy {
+    /// Leave the fill level alone; drift is only resolved by overruns and underruns.
+    Ignore,
+    /// Discard buffered frames once the fill level exceeds the target by more
+    /// than `tolerance` frames.
+    Drop { tolerance: usize },
+    /// Play slightly faster or slower (by at most `max_deviation`, e.g. 0.005 = 0.5%)
+    /// to pull the fill level back towards the target.
+    Resample { max_deviation: f32 },
+}
+
+/// Ring buffer sizing and latency settings.
+///
+/// The buffer carries interleaved frames of `channels` samples; sizes are
+/// given in frames.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RingConfig {
+    /// Maximum number of buffered frames.
+    pub capacity: usize,
+    /// Fill level, in frames, the consumer waits for before playing and steers towards.
+    pub target_latency: usize,
+    pub channels: usize,
+    pub drift: DriftPolicy,
+}
+
+impl RingConfig {
+    /// Sizes the buffer for device callbacks of `frames` frames: the target
+    /// holds two callbacks' worth so both sides can run a full block ahead.
+    pub fn for_block(frames: usize, drift: DriftPolicy) -> Self {
+        let target_latency = 2 * frames.max(1);
+        Self {
+            capacity: 8 * target_latency,
+            target_latency,
+            channels: 1,
+            drift,
+        }
+    }
+}
+
+impl Default for RingConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 8192,
+            target_latency: 1024,
+            channels: 1,
+            drift: DriftPolicy::Resample {
+                max_deviation: 0.005,
+            },
+        }
+    }
+}
+
+/// Underrun and overrun counters shared by both ends of a ring buffer.
+#[derive(Debug, Default)]
+pub struct RingStats {
+    underruns: AtomicUsize,
+    overruns: AtomicUsize,
+}
+
+impl RingStats {
+    /// Number of reads that ran out of buffered samples.
+    pub fn underruns(&self) -> usize {
+        self.underruns.load(Ordering::Relaxed)
+    }
+
+    /// Number of writes that found the buffer full and dropped samples.
+    pub fn overruns(&self) -> usize {
+        self.overruns.load(Ordering::Relaxed)
+    }
+}
+
+struct Shared {
+    // f32 samples stored as raw bits so both ends can touch them without locks.
+    slots: Box<[AtomicU32]>,
+    // Total samples written and read, always on frame boundaries; wrap around,
+    // positions are taken modulo the slot count.
+    head: AtomicUsize,
+    tail: AtomicUsize,
+    channels: usize,
+    stats: Arc<RingStats>,
+}
+
+impl Shared {
+    fn load(&self, position: usize) -> f32 {
+        f32::from_bits(self.slots[position % self.slots.len()].load(Ordering::Relaxed))
+    }
+
+    fn store(&self, position: usize, sample: f32) {
+        self.slots[position % self.slots.len()].store(sample.to_bits(), Ordering::Relaxed);
+    }
+
+    /// Buffered samples between `tail` and `head`.
+    fn used(&self) -> usize {
+        let tail = self.tail.load(Ordering::Relaxed);
+        let head = self.head.load(Ordering::Acquire);
+        head.wrapping_sub(tail)
+    }
+}
+
+/// Writing end of the ring buffer, owned by the input callback.
+pub struct Producer {
+    shared: Arc<Shared>,
+}
+
+/// Reading end of the ring buffer, owned by the output callback.
+pub struct Consumer {
+    shared: Arc<Shared>,
+    target_latency: usize,
+    drift: DriftPolicy,
+    primed: bool,
+    // Fractional read position carried between calls when resampling.
+    frac: f64,
+}
+
+/// Creates a single-producer/single-consumer lock-free ring buffer.
+pub fn ring_buffer(config: RingConfig) -> (Producer, Consumer) {
+    assert!(config.capacity > 0, "ring buffer capacity must be non-zero");
+    assert!(
+        config.channels > 0,
+        "ring buffer needs at least one channel"
+    );
+    assert!(
+        config.target_latency <= config.capacity,
+        "latency target exceeds ring buffer capacity"
+    );
+
+    let shared = Arc::new(Shared {
+        slots: (0..config.capacity * config.channels)
+            .map(|_| AtomicU32::new(0))
+            .collect(),
+        head: AtomicUsize::new(0),
+        tail: AtomicUsize::new(0),
+        channels: config.channels,
+        stats: Arc::new(RingStats::default()),
+    });
+
+    let producer = Producer {
+        shared: Arc::clone(&shared),
+    };
+    let consumer = Consumer {
+        shared,
+        target_latency: config.target_latency,
+        drift: config.drift,
+        primed: false,
+        frac: 0.0,
+    };
+    (producer, consumer)
+}
+
+impl Producer {
+    /// Appends as many whole frames from the interleaved `data` as fit and
+    /// returns how many samples were written.
+    pub fn push(&mut self, data: &[f32]) -> usize {
+        let channels = self.shared.channels;
+        let head = self.shared.head.load(Ordering::Relaxed);
+        let tail = self.shared.tail.load(Ordering::Acquire);
+        let free = self.shared.slots.len() - head.wrapping_sub(tail);
+        let count = free.min(data.len()) / channels * channels;
+
+        for (i, sample) in data[..count].iter().enumerate() {
+            self.shared.store(head.wrapping_add(i), *sample);
+        }
+        self.shared
+            .head
+            .store(head.wrapping_add(count), Ordering::Release);
+
+        if count < data.len() {
+            self.shared.stats.overruns.fetch_add(1, Ordering::Relaxed);
+        }
+        count
+    }
+
+    /// Total frames written so far.
+    pub fn position(&self) -> u64 {
+        (self.shared.head.load(Ordering::Relaxed) / self.shared.channels) as u64
+    }
+
+    /// Counters shared with the consumer.
+    pub fn stats(&self) -> Arc<RingStats> {
+        Arc::clone(&self.shared.stats)
+    }
+}
+
+impl Consumer {
+    /// Fills the interleaved `out` from the buffer, padding with silence while
+    /// priming or on underrun.
+    pub fn pop(&mut self, out: &mut [f32]) {
+        let channels = self.shared.channels;
+        let tail = self.shared.tail.load(Ordering::Relaxed);
+        let head = self.shared.head.load(Ordering::Acquire);
+        let mut available = head.wrapping_sub(tail);
+        let target = self.target_latency * channels;
+
+        if !self.primed {
+            if available < target.max(channels) {
+                out.fill(0.0);
+                return;
+            }
+            self.primed = true;
+        }
+
+        let mut tail = tail;
+        match self.drift {
+            DriftPolicy::Ignore => {}
+            DriftPolicy::Drop { tolerance } => {
+                if available > target + tolerance * channels {
+                    let excess = available - target;
+                    tail = tail.wrapping_add(excess);
+                    available -= excess;
+                }
+            }
+            DriftPolicy::Resample { max_deviation } => {
+                if let Some(consumed) = self.pop_resampled(tail, available, out, max_deviation) {
+                    self.shared
+                        .tail
+                        .store(tail.wrapping_add(consumed), Ordering::Release);
+                    return;
+                }
+            }
+        }
+
+        let count = available.min(out.len()) / channels * channels;
+        for (i, sample) in out[..count].iter_mut().enumerate() {
+            *sample = self.shared.load(tail.wrapping_add(i));
+        }
+        out[count..].fill(0.0);
+        self.shared
+            .tail
+            .store(tail.wrapping_add(count), Ordering::Release);
+
+        if count < out.len() {
+            self.underrun();
+        }
+    }
+
+    /// Reads `out` at a rate nudged towards the latency target using linear
+    /// interpolation between frames. Returns the number of samples consumed,
+    /// or `None` if there is not enough data and the plain path should handle
+    /// the underrun.
+    fn pop_resampled(
+        &mut self,
+        tail: usize,
+        available: usize,
+        out: &mut [f32],
+        max_deviation: f32,
+    ) -> Option<usize> {
+        let channels = self.shared.channels;
+        let available = available / channels;
+        let target = self.target_latency.max(1) as f64;
+        let error = (available as f64 - target) / target;
+        let max_deviation = max_deviation as f64;
+        let ratio = 1.0 + (error * max_deviation).clamp(-max_deviation, max_deviation);
+
+        let end = self.frac + (out.len() / channels) as f64 * ratio;
+        // One extra frame is needed to interpolate the last output.
+        if end.floor() as usize + 1 > available {
+            self.frac = 0.0;
+            return None;
+        }
+
+        for (i, frame) in out.chunks_exact_mut(channels).enumerate() {
+            let position = self.frac + i as f64 * ratio;
+            let index = position.floor() as usize;
+            let t = (position - index as f64) as f32;
+            for (channel, sample) in frame.iter_mut().enumerate() {
+                let s1 = self
+                    .shared
+                    .load(tail.wrapping_add(index * channels + channel));
+                let s2 = self
+                    .shared
+                    .load(tail.wrapping_add((index + 1) * channels + channel));
+                *sample = s1 + t * (s2 - s1);
+            }
+        }
+
+        let consumed = end.floor();
+        self.frac = end - consumed;
+        Some(consumed as usize * channels)
+    }
+
+    fn underrun(&mut self) {
+        self.shared.stats.underruns.fetch_add(1, Ordering::Relaxed);
+        // Wait for the buffer to refill to the target before playing again.
+        self.primed = false;
+    }
+
+    /// Number of frames currently buffered.
+    pub fn len(&self) -> usize {
+        self.shared.used() / self.shared.channels
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Total frames read (or skipped) so far.
+    pub fn position(&self) -> u64 {
+        (self.shared.tail.load(Ordering::Relaxed) / self.shared.channels) as u64
+    }
+
+    /// Counters shared with the producer.
+    pub fn stats(&self) -> Arc<RingStats> {
+        Arc::clone(&self.shared.stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(capacity: usize, target_latency: usize, drift: DriftPolicy) -> RingConfig {
+        RingConfig {
+            capacity,
+            target_latency,
+            channels: 1,
+            drift,
+        }
+    }
+
+    #[test]
+    fn pops_samples_in_order() {
+        let (mut producer, mut consumer) = ring_buffer(config(8, 1, DriftPolicy::Ignore));
+        let mut out = [0.0; 3];
+
+        for round in 0..5 {
+            let base = round as f32 * 3.0;
+            assert_eq!(producer.push(&[base, base + 1.0, base + 2.0]), 3);
+            consumer.pop(&mut out);
+            assert_eq!(out, [base, base + 1.0, base + 2.0]);
+        }
+        assert_eq!(consumer.stats().underruns(), 0);
+    }
+
+    #[test]
+    fn counts_overruns_and_drops_excess() {
+        let (mut producer, consumer) = ring_buffer(config(4, 1, DriftPolicy::Ignore));
+
+        assert_eq!(producer.push(&[1.0, 2.0, 3.0]), 3);
+        assert_eq!(producer.push(&[4.0, 5.0, 6.0]), 1);
+        assert_eq!(consumer.len(), 4);
+        assert_eq!(producer.stats().overruns(), 1);
+    }
+
+    #[test]
+    fn waits_for_latency_target_and_counts_underruns() {
+        let (mut producer, mut consumer) = ring_buffer(config(16, 4, DriftPolicy::Ignore));
+        let mut out = [1.0; 3];
+
+        producer.push(&[1.0, 2.0]);
+        consumer.pop(&mut out);
+        assert_eq!(out, [0.0; 3]);
+        assert_eq!(consumer.len(), 2);
+
+        producer.push(&[3.0, 4.0]);
+        consumer.pop(&mut out);
+        assert_eq!(out, [1.0, 2.0, 3.0]);
+
+        consumer.pop(&mut out);
+        assert_eq!(out, [4.0, 0.0, 0.0]);
+        assert_eq!(consumer.stats().underruns(), 1);
+    }
+
+    #[test]
+    fn drop_policy_trims_fill_level_to_target() {
+        let drift = DriftPolicy::Drop { tolerance: 2 };
+        let (mut producer, mut consumer) = ring_buffer(config(32, 4, drift));
+        let mut out = [0.0; 2];
+
+        producer.push(&[0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0]);
+        consumer.pop(&mut out);
+        assert_eq!(out, [4.0, 5.0]);
+        assert_eq!(consumer.len(), 2);
+    }
+
+    #[test]
+    fn resample_policy_drains_faster_when_over_target() {
+        let drift = DriftPolicy::Resample {
+            max_deviation: 0.01,
+        };
+        let (mut producer, mut consumer) = ring_buffer(config(4096, 256, drift));
+        let ramp: Vec<f32> = (0..2048).map(|i| i as f32).collect();
+        producer.push(&ramp);
+
+        let mut out = [0.0; 1000];
+        consumer.pop(&mut out);
+        // 1% faster playback; the exact count depends on float rounding.
+        assert!((1009..=1010).contains(&(2048 - consumer.len())));
+        assert!((out[500] - 505.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn keeps_frames_of_interleaved_channels_together() {
+        let ring = RingConfig {
+            capacity: 4,
+            target_latency: 1,
+            channels: 2,
+            drift: DriftPolicy::Ignore,
+        };
+        let (mut producer, mut consumer) = ring_buffer(ring);
+
+        assert_eq!(producer.push(&[1.0, -1.0, 2.0, -2.0, 3.0, -3.0]), 6);
+        assert_eq!(producer.push(&[4.0, -4.0, 5.0, -5.0]), 2);
+        assert_eq!(consumer.len(), 4);
+
+        let mut out = [0.0; 6];
+        consumer.pop(&mut out);
+        assert_eq!(out, [1.0, -1.0, 2.0, -2.0, 3.0, -3.0]);
+    }
+
+    #[test]
+    fn resamples_each_channel_separately() {
+        let ring = RingConfig {
+            capacity: 4096,
+            target_latency: 256,
+            channels: 2,
+            drift: DriftPolicy::Resample {
+                max_deviation: 0.01,
+            },
+        };
+        let (mut producer, mut consumer) = ring_buffer(ring);
+        let frames: Vec<f32> = (0..2048).flat_map(|i| [i as f32, -(i as f32)]).collect();
+        producer.push(&frames);
+
+        let mut out = [0.0; 2000];
+        consumer.pop(&mut out);
+        for frame in out.chunks_exact(2) {
+            assert_eq!(frame[0], -frame[1]);
+        }
+        assert!((out[1000] - 505.0).abs() < 1e-3);
+    }
+}
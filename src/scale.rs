@@ -0,0 +1,184 @@
+use crate::pitch_detection::Note;
+use anyhow::{Result, bail};
+use std::fmt;
+use std::str::FromStr;
+
+/// Semitone steps of the major scale from its root.
+const MAJOR: [usize; 7] = [0, 2, 4, 5, 7, 9, 11];
+/// Semitone steps of the natural minor scale from its root.
+const MINOR: [usize; 7] = [0, 2, 3, 5, 7, 8, 10];
+
+/// Set of pitch classes that notes are allowed to land on.
+///
+/// Parsed from `chromatic`, a key such as `C major`, `F# minor` or `Bb`
+/// (major), or a list of note names such as `C D E G A`. Single notes can
+/// then be switched on and off with [`Scale::set_note`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Scale {
+    // One bit per pitch class, C in bit 0 up to B in bit 11.
+    notes: u16,
+}
+
+impl Scale {
+    pub const CHROMATIC: Scale = Scale { notes: 0xfff };
+
+    pub fn major(root: usize) -> Self {
+        Self::from_steps(root, &MAJOR)
+    }
+
+    pub fn minor(root: usize) -> Self {
+        Self::from_steps(root, &MINOR)
+    }
+
+    fn from_steps(root: usize, steps: &[usize]) -> Self {
+        let notes = steps
+            .iter()
+            .fold(0, |notes, step| notes | 1 << ((root + step) % 12));
+        Self { notes }
+    }
+
+    /// Whether pitch class `pitch_class` (0 for C up to 11 for B) is enabled.
+    pub fn contains(&self, pitch_class: usize) -> bool {
+        self.notes & 1 << (pitch_class % 12) != 0
+    }
+
+    pub fn set_note(&mut self, pitch_class: usize, enabled: bool) {
+        let bit = 1 << (pitch_class % 12);
+        if enabled {
+            self.notes |= bit;
+        } else {
+            self.notes &= !bit;
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.notes == 0
+    }
+
+    /// Enabled note closest to `position`, a fractional MIDI note number.
+    pub fn nearest(&self, position: f32) -> Option<Note> {
+        if self.is_empty() {
+            return None;
+        }
+        let below = position.floor() as i32;
+        (0..=6)
+            .flat_map(|distance| [below - distance, below + 1 + distance])
+            .map(Note)
+            .filter(|note| self.contains(note.pitch_class()))
+            .min_by(|a, b| {
+                (a.0 as f32 - position)
+                    .abs()
+                    .total_cmp(&(b.0 as f32 - position).abs())
+            })
+    }
+
+    /// The note `steps` scale degrees away from `note`, which is snapped to
+    /// the scale first. Negative steps go down.
+    pub fn step(&self, note: Note, steps: i32) -> Option<Note> {
+        let mut note = self.nearest(note.0 as f32)?;
+        for _ in 0..steps.unsigned_abs() {
+            loop {
+                note.0 += steps.signum();
+                if self.contains(note.pitch_class()) {
+                    break;
+                }
+            }
+        }
+        Some(note)
+    }
+}
+
+impl Default for Scale {
+    fn default() -> Self {
+        Self::CHROMATIC
+    }
+}
+
+impl FromStr for Scale {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let words: Vec<&str> = s.split_whitespace().collect();
+        match words.as_slice() {
+            [] => bail!("Empty scale"),
+            [word] if word.eq_ignore_ascii_case("chromatic") => Ok(Self::CHROMATIC),
+            [root] => Ok(Self::major(parse_pitch_class(root)?)),
+            [root, kind] if kind.eq_ignore_ascii_case("major") => {
+                Ok(Self::major(parse_pitch_class(root)?))
+            }
+            [root, kind] if kind.eq_ignore_ascii_case("minor") => {
+                Ok(Self::minor(parse_pitch_class(root)?))
+            }
+            names => {
+                let mut scale = Scale { notes: 0 };
+                for name in names {
+                    scale.set_note(parse_pitch_class(name)?, true);
+                }
+                Ok(scale)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Scale {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<&str> = (0..12)
+            .filter(|&pitch_class| self.contains(pitch_class))
+            .map(|pitch_class| Note::NAMES[pitch_class])
+            .collect();
+        write!(f, "{}", names.join(" "))
+    }
+}
+
+/// Parses a note name such as `C`, `f#` or `Bb` into its pitch class.
+pub fn parse_pitch_class(name: &str) -> Result<usize> {
+    let mut chars = name.chars();
+    let natural = match chars.next().map(|c| c.to_ascii_uppercase()) {
+        Some('C') => 0,
+        Some('D') => 2,
+        Some('E') => 4,
+        Some('F') => 5,
+        Some('G') => 7,
+        Some('A') => 9,
+        Some('B') => 11,
+        _ => bail!("Unknown note: {name}"),
+    };
+    let pitch_class = match chars.as_str() {
+        "" => natural,
+        "#" => natural + 1,
+        "b" => natural + 11,
+        _ => bail!("Unknown note: {name}"),
+    };
+    Ok(pitch_class % 12)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_keys_and_note_lists() {
+        assert_eq!(
+            "C major".parse::<Scale>().unwrap().to_string(),
+            "C D E F G A B"
+        );
+        assert_eq!("a minor".parse::<Scale>().unwrap(), Scale::major(0));
+        assert_eq!("Bb".parse::<Scale>().unwrap(), Scale::minor(7));
+        assert_eq!("C D# G".parse::<Scale>().unwrap().to_string(), "C D# G");
+        assert_eq!("chromatic".parse::<Scale>().unwrap(), Scale::CHROMATIC);
+        assert!("H major".parse::<Scale>().is_err());
+    }
+
+    #[test]
+    fn snaps_to_enabled_notes() {
+        let mut scale = Scale::major(0);
+        assert_eq!(scale.nearest(60.9), Some(Note(60)));
+        assert_eq!(scale.nearest(61.2), Some(Note(62)));
+        assert_eq!(scale.step(Note(60), 2), Some(Note(64)));
+        assert_eq!(scale.step(Note(60), -1), Some(Note(59)));
+
+        scale.set_note(2, false);
+        assert_eq!(scale.nearest(61.2), Some(Note(60)));
+        assert_eq!(Scale { notes: 0 }.nearest(60.0), None);
+    }
+}
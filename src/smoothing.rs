@@ -0,0 +1,180 @@
+use std::time::Duration;
+
+/// Shape and duration of the transition when a parameter changes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Ramp {
+    /// Jump straight to the new value.
+    None,
+    /// Move at a constant rate, arriving after the given time.
+    Linear(Duration),
+    /// Approach the new value exponentially with the given time constant
+    /// (about 63% of the way after one time constant).
+    Exponential(Duration),
+}
+
+/// A parameter value that glides towards its target one sample at a time.
+#[derive(Debug, Clone)]
+pub struct SmoothedParam {
+    current: f32,
+    target: f32,
+    ramp: Ramp,
+    sample_rate: f32,
+    // Linear ramp: per-sample increment and samples left to go.
+    step: f32,
+    remaining: usize,
+    // Exponential ramp: per-sample decay of the distance to the target.
+    decay: f32,
+}
+
+impl SmoothedParam {
+    pub fn new(value: f32, ramp: Ramp, sample_rate: u32) -> Self {
+        let mut param = Self {
+            current: value,
+            target: value,
+            ramp,
+            sample_rate: sample_rate as f32,
+            step: 0.0,
+            remaining: 0,
+            decay: 0.0,
+        };
+        param.set_ramp(ramp);
+        param
+    }
+
+    /// Changes the ramp used for future target changes.
+    pub fn set_ramp(&mut self, ramp: Ramp) {
+        self.ramp = ramp;
+        self.decay = match ramp {
+            Ramp::Exponential(time) if !time.is_zero() => {
+                (-1.0 / (time.as_secs_f32() * self.sample_rate)).exp()
+            }
+            _ => 0.0,
+        };
+        self.set_target(self.target);
+    }
+
+    /// Changes the sample rate that ramp times are measured in.
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate as f32;
+        self.set_ramp(self.ramp);
+    }
+
+    pub fn ramp(&self) -> Ramp {
+        self.ramp
+    }
+
+    /// Starts gliding from the current value towards `target`.
+    pub fn set_target(&mut self, target: f32) {
+        self.target = target;
+        match self.ramp {
+            Ramp::Linear(time) => {
+                self.remaining = (time.as_secs_f32() * self.sample_rate).round() as usize;
+                self.step = if self.remaining == 0 {
+                    0.0
+                } else {
+                    (target - self.current) / self.remaining as f32
+                };
+                if self.remaining == 0 {
+                    self.current = target;
+                }
+            }
+            Ramp::None => self.current = target,
+            Ramp::Exponential(_) => {}
+        }
+    }
+
+    /// Jumps to `value` immediately, cancelling any glide.
+    pub fn reset(&mut self, value: f32) {
+        self.current = value;
+        self.target = value;
+        self.remaining = 0;
+    }
+
+    pub fn value(&self) -> f32 {
+        self.current
+    }
+
+    pub fn target(&self) -> f32 {
+        self.target
+    }
+
+    pub fn is_smoothing(&self) -> bool {
+        self.current != self.target
+    }
+
+    /// Advances one sample and returns the new value.
+    pub fn next_value(&mut self) -> f32 {
+        self.skip(1)
+    }
+
+    /// Advances `samples` samples at once and returns the new value.
+    pub fn skip(&mut self, samples: usize) -> f32 {
+        if !self.is_smoothing() || samples == 0 {
+            return self.current;
+        }
+
+        match self.ramp {
+            Ramp::Linear(_) => {
+                if samples >= self.remaining {
+                    self.current = self.target;
+                    self.remaining = 0;
+                } else {
+                    self.current += self.step * samples as f32;
+                    self.remaining -= samples;
+                }
+            }
+            Ramp::Exponential(_) => {
+                let distance = (self.current - self.target) * self.decay.powi(samples as i32);
+                // Snap once the remaining distance is inaudible.
+                self.current = if distance.abs() < 1e-5 {
+                    self.target
+                } else {
+                    self.target + distance
+                };
+            }
+            Ramp::None => self.current = self.target,
+        }
+        self.current
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn linear_ramp_arrives_on_time() {
+        let mut param = SmoothedParam::new(0.0, Ramp::Linear(Duration::from_millis(10)), 1000);
+        param.set_target(1.0);
+
+        let values: Vec<f32> = (0..10).map(|_| param.next_value()).collect();
+        assert!(values.windows(2).all(|w| w[1] > w[0]));
+        assert!((values[4] - 0.5).abs() < 1e-6);
+        assert_eq!(values[9], 1.0);
+        assert!(!param.is_smoothing());
+    }
+
+    #[test]
+    fn exponential_ramp_follows_time_constant() {
+        let mut param = SmoothedParam::new(0.0, Ramp::Exponential(Duration::from_millis(10)), 1000);
+        param.set_target(1.0);
+
+        assert!((param.skip(10) - (1.0 - (-1.0_f32).exp())).abs() < 1e-4);
+        param.skip(1000);
+        assert_eq!(param.value(), 1.0);
+    }
+
+    #[test]
+    fn retargeting_continues_from_current_value() {
+        let mut param = SmoothedParam::new(0.0, Ramp::Linear(Duration::from_millis(10)), 1000);
+        param.set_target(1.0);
+        param.skip(5);
+
+        param.set_target(0.0);
+        assert!((param.next_value() - 0.45).abs() < 1e-6);
+
+        param.set_ramp(Ramp::None);
+        param.set_target(2.0);
+        assert_eq!(param.value(), 2.0);
+    }
+}
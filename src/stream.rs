@@ -0,0 +1,967 @@
+use crate::channels::ChannelMatrix;
+use crate::devices::{self, DeviceSelector};
+use crate::error::AudioError;
+use crate::format::{self, Dither};
+use crate::latency::LatencyMonitor;
+use crate::processor::{AudioProcessor, MAX_BLOCK_FRAMES};
+use crate::resample::Resampler;
+use crate::ring_buffer::{Consumer, DriftPolicy, Producer, RingConfig, RingStats, ring_buffer};
+use cpal::traits::{DeviceTrait, StreamTrait};
+use cpal::{FromSample, Sample, SampleFormat, SizedSample};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// How long to wait for a torn-down output stream to hand its processor back.
+const RECLAIM_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Calls `$build::<T>(args...)` with `T` matching the runtime sample format,
+/// for the formats [`format::is_supported`] accepts.
+macro_rules! with_sample_type {
+    ($format:expr, $build:ident($($arg:expr),* $(,)?)) => {
+        match $format {
+            SampleFormat::I8 => $build::<i8>($($arg),*),
+            SampleFormat::I16 => $build::<i16>($($arg),*),
+            SampleFormat::I32 => $build::<i32>($($arg),*),
+            SampleFormat::U8 => $build::<u8>($($arg),*),
+            SampleFormat::U16 => $build::<u16>($($arg),*),
+            SampleFormat::U32 => $build::<u32>($($arg),*),
+            SampleFormat::F32 => $build::<f32>($($arg),*),
+            SampleFormat::F64 => $build::<f64>($($arg),*),
+            format => Err(AudioError::UnsupportedConfig(format!(
+                "Unsupported sample format {format}"
+            ))),
+        }
+    };
+}
+
+/// What [`RunningStreams`] does when a device disappears mid-stream.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReconnectPolicy {
+    /// Attempts to reopen the devices before giving up; `None` keeps trying
+    /// until the streams are dropped.
+    pub max_attempts: Option<u32>,
+    /// Pause before each attempt.
+    pub retry_interval: Duration,
+}
+
+impl ReconnectPolicy {
+    /// Gives up as soon as a device disappears.
+    pub const NEVER: Self = Self {
+        max_attempts: Some(0),
+        retry_interval: Duration::ZERO,
+    };
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: None,
+            retry_interval: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Health of a [`RunningStreams`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamStatus {
+    Running,
+    /// A device went away and is being reopened.
+    Reconnecting {
+        attempt: u32,
+    },
+    /// A device went away and the [`ReconnectPolicy`] gave up on it.
+    Disconnected,
+}
+
+/// Capture and playback devices with the configs they will be opened with.
+#[derive(Clone)]
+pub struct StreamHost {
+    host_id: cpal::HostId,
+    input_selector: DeviceSelector,
+    output_selector: DeviceSelector,
+    input_device: cpal::Device,
+    output_device: cpal::Device,
+    input_config: cpal::StreamConfig,
+    output_config: cpal::StreamConfig,
+    input_format: SampleFormat,
+    output_format: SampleFormat,
+    input_buffer_range: cpal::SupportedBufferSize,
+    output_buffer_range: cpal::SupportedBufferSize,
+    dither: bool,
+    matrix: ChannelMatrix,
+    reconnect: ReconnectPolicy,
+}
+
+/// Input and output streams that keep running until dropped.
+///
+/// The streams live on a supervisor thread, which rebuilds them with the
+/// same processor if a device reports that it has gone away.
+pub struct RunningStreams {
+    shared: Arc<StreamShared>,
+    events: Sender<StreamEvent>,
+    supervisor: Option<JoinHandle<()>>,
+}
+
+/// State the supervisor publishes for [`RunningStreams`]; replaced
+/// wholesale when the streams are rebuilt.
+struct StreamShared {
+    status: Mutex<StreamStatus>,
+    stats: Mutex<Arc<RingStats>>,
+    latency: Mutex<Arc<LatencyMonitor>>,
+    processor_latency: Mutex<Duration>,
+}
+
+impl StreamShared {
+    fn set<T>(field: &Mutex<T>, value: T) {
+        *field.lock().unwrap_or_else(PoisonError::into_inner) = value;
+    }
+
+    fn get<T: Clone>(field: &Mutex<T>) -> T {
+        field.lock().unwrap_or_else(PoisonError::into_inner).clone()
+    }
+}
+
+enum StreamEvent {
+    /// Error reported by the streams of the given build.
+    Error(u32, cpal::StreamError),
+    Stop,
+}
+
+/// A live input/output stream pair.
+struct Streams {
+    _input: cpal::Stream,
+    _output: cpal::Stream,
+}
+
+/// Owns the processor inside the output callback and hands it back when the
+/// callback is dropped, so rebuilt streams carry on with the same one.
+struct Reclaim {
+    processor: Option<Box<dyn AudioProcessor>>,
+    home: Sender<Box<dyn AudioProcessor>>,
+}
+
+impl Drop for Reclaim {
+    fn drop(&mut self) {
+        if let Some(processor) = self.processor.take() {
+            let _ = self.home.send(processor);
+        }
+    }
+}
+
+/// Input callback state: mixes captured blocks to the output layout,
+/// resamples them to the output rate and queues them for playback. Every
+/// buffer is sized up front for blocks of [`MAX_BLOCK_FRAMES`].
+struct Capture {
+    producer: Producer,
+    matrix: ChannelMatrix,
+    mixed: Vec<f32>,
+    resampler: Option<Resampler>,
+    resampled: Vec<f32>,
+    // The resampler holds back this much captured audio before emitting it.
+    resampler_delay: Duration,
+    latency: Arc<LatencyMonitor>,
+}
+
+impl Capture {
+    fn new(
+        producer: Producer,
+        matrix: ChannelMatrix,
+        input_rate: u32,
+        output_rate: u32,
+        latency: Arc<LatencyMonitor>,
+    ) -> Self {
+        let channels = matrix.outputs();
+        // Convert captured audio to the playback rate so it plays at the right speed.
+        let mut resampler =
+            (input_rate != output_rate).then(|| Resampler::new(input_rate, output_rate, channels));
+        let resampled_frames = resampler.as_mut().map_or(0, |resampler| {
+            resampler.reserve(MAX_BLOCK_FRAMES);
+            resampler.max_output_frames(MAX_BLOCK_FRAMES)
+        });
+        let resampler_delay = resampler.as_ref().map_or(Duration::ZERO, |r| {
+            Duration::from_secs_f64(r.latency() as f64 / input_rate as f64)
+        });
+        Self {
+            producer,
+            matrix,
+            mixed: Vec::with_capacity(MAX_BLOCK_FRAMES * channels),
+            resampler,
+            resampled: Vec::with_capacity(resampled_frames * channels),
+            resampler_delay,
+            latency,
+        }
+    }
+
+    /// Queues `data`, at most [`MAX_BLOCK_FRAMES`] frames in the input
+    /// layout, captured at `timestamp`.
+    fn write(&mut self, data: &[f32], timestamp: Option<Instant>) {
+        if let Some(capture) = timestamp.and_then(|t| t.checked_sub(self.resampler_delay)) {
+            self.latency
+                .record_capture(self.producer.position(), capture);
+        }
+        let frames = data.len() / self.matrix.inputs();
+        self.mixed.resize(frames * self.matrix.outputs(), 0.0);
+        self.matrix.apply(data, &mut self.mixed);
+        match self.resampler.as_mut() {
+            Some(resampler) => {
+                self.resampled.clear();
+                resampler.process(&self.mixed, &mut self.resampled);
+                self.producer.push(&self.resampled);
+            }
+            None => {
+                self.producer.push(&self.mixed);
+            }
+        }
+    }
+}
+
+/// Output callback state: pulls captured audio from the ring buffer and
+/// runs the processor over it.
+struct Playback {
+    consumer: Consumer,
+    reclaim: Reclaim,
+    latency: Arc<LatencyMonitor>,
+}
+
+impl Playback {
+    /// Fills `output`, at most [`MAX_BLOCK_FRAMES`] frames, due to be
+    /// heard at `timestamp`.
+    fn render(&mut self, output: &mut [f32], timestamp: Option<Instant>) {
+        if let Some(playback) = timestamp {
+            self.latency
+                .record_playback(self.consumer.position(), playback);
+        }
+        self.consumer.pop(output);
+        if let Some(processor) = self.reclaim.processor.as_mut() {
+            processor.process(output);
+        }
+    }
+}
+
+impl StreamHost {
+    /// Uses the default input and output devices of the default host.
+    pub fn with_default_devices() -> Result<Self, AudioError> {
+        Self::new(
+            &cpal::default_host(),
+            &DeviceSelector::Default,
+            &DeviceSelector::Default,
+        )
+    }
+
+    /// Opens the devices of `host` picked by `input` and `output` with their
+    /// default configs, falling back to another sample format if needed.
+    pub fn new(
+        host: &cpal::Host,
+        input: &DeviceSelector,
+        output: &DeviceSelector,
+    ) -> Result<Self, AudioError> {
+        let input_device = devices::input_device(host, input)?;
+        let output_device = devices::output_device(host, output)?;
+
+        let input_config = (|| {
+            let supported: Vec<_> = input_device.supported_input_configs()?.collect();
+            format::choose_config(input_device.default_input_config()?, &supported)
+        })()
+        .map_err(|err| not_available(err, &input_device))?;
+        let output_config = (|| {
+            let supported: Vec<_> = output_device.supported_output_configs()?.collect();
+            format::choose_config(output_device.default_output_config()?, &supported)
+        })()
+        .map_err(|err| not_available(err, &output_device))?;
+
+        let input_format = input_config.sample_format();
+        let output_format = output_config.sample_format();
+        let input_buffer_range = *input_config.buffer_size();
+        let output_buffer_range = *output_config.buffer_size();
+        let input_config = input_config.config();
+        let output_config = output_config.config();
+        let matrix = ChannelMatrix::default_for(
+            input_config.channels as usize,
+            output_config.channels as usize,
+        );
+
+        Ok(Self {
+            host_id: host.id(),
+            input_selector: input.clone(),
+            output_selector: output.clone(),
+            input_device,
+            output_device,
+            input_config,
+            output_config,
+            input_format,
+            output_format,
+            input_buffer_range,
+            output_buffer_range,
+            dither: false,
+            matrix,
+            reconnect: ReconnectPolicy::default(),
+        })
+    }
+
+    /// Opens the same devices again, keeping this host's settings where the
+    /// devices still support them.
+    fn reopen(&self) -> Result<Self, AudioError> {
+        let host = cpal::host_from_id(self.host_id)?;
+        let mut reopened = Self::new(&host, &self.input_selector, &self.output_selector)?;
+        reopened.dither = self.dither;
+        reopened.reconnect = self.reconnect;
+        if let Some(frames) = self.buffer_frames() {
+            let _ = reopened.set_buffer_frames(frames);
+        }
+        let _ = reopened.set_channel_matrix(self.matrix.clone());
+        Ok(reopened)
+    }
+
+    pub fn input_name(&self) -> String {
+        self.input_device.name().unwrap_or_default()
+    }
+
+    pub fn output_name(&self) -> String {
+        self.output_device.name().unwrap_or_default()
+    }
+
+    pub fn input_config(&self) -> &cpal::StreamConfig {
+        &self.input_config
+    }
+
+    pub fn output_config(&self) -> &cpal::StreamConfig {
+        &self.output_config
+    }
+
+    pub fn input_format(&self) -> SampleFormat {
+        self.input_format
+    }
+
+    pub fn output_format(&self) -> SampleFormat {
+        self.output_format
+    }
+
+    /// Requests device callbacks of `frames` frames on both streams.
+    ///
+    /// Devices that report their supported range must include `frames`;
+    /// devices that don't report one keep their default buffer size.
+    pub fn set_buffer_frames(&mut self, frames: u32) -> Result<(), AudioError> {
+        let sides = [
+            ("Input", &self.input_buffer_range, &mut self.input_config),
+            ("Output", &self.output_buffer_range, &mut self.output_config),
+        ];
+        for (side, range, config) in sides {
+            match *range {
+                cpal::SupportedBufferSize::Range { min, max } if (min..=max).contains(&frames) => {
+                    config.buffer_size = cpal::BufferSize::Fixed(frames);
+                }
+                cpal::SupportedBufferSize::Range { min, max } => {
+                    return Err(AudioError::UnsupportedConfig(format!(
+                        "{side} device supports buffers of {min}-{max} frames, not {frames}"
+                    )));
+                }
+                cpal::SupportedBufferSize::Unknown => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Fixed output callback size, if one was set and the device supports it.
+    pub fn buffer_frames(&self) -> Option<u32> {
+        match self.output_config.buffer_size {
+            cpal::BufferSize::Fixed(frames) => Some(frames),
+            cpal::BufferSize::Default => None,
+        }
+    }
+
+    /// Ring buffer sized for this host's output callbacks, or the default
+    /// ring when the device picks its own buffer size.
+    pub fn ring_config(&self, drift: DriftPolicy) -> RingConfig {
+        match self.buffer_frames() {
+            Some(frames) => RingConfig::for_block(frames as usize, drift),
+            None => RingConfig {
+                drift,
+                ..RingConfig::default()
+            },
+        }
+    }
+
+    /// Adds TPDF dither when playing to an 8- or 16-bit integer device.
+    pub fn set_dither(&mut self, enabled: bool) {
+        self.dither = enabled;
+    }
+
+    /// Routing from the input device's channels to the output device's.
+    pub fn channel_matrix(&self) -> &ChannelMatrix {
+        &self.matrix
+    }
+
+    /// Replaces the default up/downmix between input and output channels.
+    pub fn set_channel_matrix(&mut self, matrix: ChannelMatrix) -> Result<(), AudioError> {
+        if matrix.inputs() != self.input_config.channels as usize
+            || matrix.outputs() != self.output_config.channels as usize
+        {
+            return Err(AudioError::UnsupportedConfig(format!(
+                "Channel matrix is {}x{}, devices need {}x{}",
+                matrix.inputs(),
+                matrix.outputs(),
+                self.input_config.channels,
+                self.output_config.channels
+            )));
+        }
+        self.matrix = matrix;
+        Ok(())
+    }
+
+    /// What to do when a device disappears while streaming.
+    pub fn set_reconnect_policy(&mut self, policy: ReconnectPolicy) {
+        self.reconnect = policy;
+    }
+
+    /// Starts capture and playback, running the interleaved output through
+    /// `processor` after preparing it for the output format.
+    ///
+    /// If a device goes away the streams are rebuilt following the
+    /// [`ReconnectPolicy`]; the processor is prepared again for the reopened
+    /// devices' format.
+    pub fn start(
+        &self,
+        processor: impl AudioProcessor + 'static,
+        ring: RingConfig,
+    ) -> Result<RunningStreams, AudioError> {
+        let shared = Arc::new(StreamShared {
+            status: Mutex::new(StreamStatus::Running),
+            stats: Mutex::default(),
+            latency: Mutex::new(Arc::new(LatencyMonitor::new(
+                self.output_config.sample_rate.0,
+            ))),
+            processor_latency: Mutex::default(),
+        });
+        let (events, event_rx) = mpsc::channel();
+        let (started, started_rx) = mpsc::channel();
+
+        let supervisor = {
+            let host = self.clone();
+            let shared = Arc::clone(&shared);
+            let events = events.clone();
+            let processor: Box<dyn AudioProcessor> = Box::new(processor);
+            thread::spawn(move || {
+                supervise(host, processor, ring, &shared, events, event_rx, started)
+            })
+        };
+        // The supervisor only exits early if the first build failed.
+        started_rx
+            .recv()
+            .unwrap_or(Err(AudioError::DeviceDisconnected))?;
+
+        Ok(RunningStreams {
+            shared,
+            events,
+            supervisor: Some(supervisor),
+        })
+    }
+
+    /// Builds and starts both streams. The processor travels inside a
+    /// [`Reclaim`], so it comes back through `home` whether or not this
+    /// succeeds.
+    fn open(
+        &self,
+        reclaim: Reclaim,
+        ring: RingConfig,
+        shared: &StreamShared,
+        generation: u32,
+        events: &Sender<StreamEvent>,
+    ) -> Result<Streams, AudioError> {
+        let mut reclaim = reclaim;
+        let channels = self.output_config.channels as usize;
+        let (producer, consumer) = ring_buffer(RingConfig { channels, ..ring });
+        let input_rate = self.input_config.sample_rate.0;
+        let output_rate = self.output_config.sample_rate.0;
+        let latency = Arc::new(LatencyMonitor::new(output_rate));
+
+        let mut capture = Capture::new(
+            producer,
+            self.matrix.clone(),
+            input_rate,
+            output_rate,
+            Arc::clone(&latency),
+        );
+        let on_input_error = error_callback(events, generation);
+        let input = with_sample_type!(
+            self.input_format,
+            build_input(
+                &self.input_device,
+                &self.input_config,
+                move |data, timestamp| capture.write(data, timestamp),
+                on_input_error
+            )
+        )?;
+
+        let mut processor_latency = 0;
+        if let Some(processor) = reclaim.processor.as_mut() {
+            processor.prepare(output_rate, channels);
+            processor_latency = processor.latency();
+        }
+        let stats = consumer.stats();
+        let mut playback = Playback {
+            consumer,
+            reclaim,
+            latency: Arc::clone(&latency),
+        };
+        let on_output_error = error_callback(events, generation);
+        let dither = Dither::new(self.output_format, self.dither);
+        let output = with_sample_type!(
+            self.output_format,
+            build_output(
+                &self.output_device,
+                &self.output_config,
+                dither,
+                move |block, timestamp| playback.render(block, timestamp),
+                on_output_error
+            )
+        )?;
+
+        input.play()?;
+        output.play()?;
+
+        StreamShared::set(&shared.stats, stats);
+        StreamShared::set(&shared.latency, latency);
+        StreamShared::set(
+            &shared.processor_latency,
+            Duration::from_secs_f64(processor_latency as f64 / output_rate as f64),
+        );
+        Ok(Streams {
+            _input: input,
+            _output: output,
+        })
+    }
+}
+
+/// Runs on the supervisor thread: owns the streams, and rebuilds them when a
+/// device reports that it is no longer available.
+fn supervise(
+    mut host: StreamHost,
+    processor: Box<dyn AudioProcessor>,
+    ring: RingConfig,
+    shared: &StreamShared,
+    events: Sender<StreamEvent>,
+    event_rx: Receiver<StreamEvent>,
+    started: Sender<Result<(), AudioError>>,
+) {
+    let (home, reclaimed) = mpsc::channel();
+    let mut generation = 0;
+    let reclaim = Reclaim {
+        processor: Some(processor),
+        home: home.clone(),
+    };
+    let mut streams = match host.open(reclaim, ring, shared, generation, &events) {
+        Ok(streams) => {
+            let _ = started.send(Ok(()));
+            streams
+        }
+        Err(err) => {
+            let _ = started.send(Err(err));
+            return;
+        }
+    };
+
+    loop {
+        match event_rx.recv() {
+            Ok(StreamEvent::Error(from, cpal::StreamError::DeviceNotAvailable))
+                if from == generation =>
+            {
+                eprintln!(" Audio device disconnected");
+                drop(streams);
+                generation += 1;
+                let reopened = reconnect(
+                    &host, &reclaimed, &home, ring, shared, generation, &events, &event_rx,
+                );
+                match reopened {
+                    Some((reopened_host, reopened_streams)) => {
+                        eprintln!(" Audio device reconnected");
+                        StreamShared::set(&shared.status, StreamStatus::Running);
+                        host = reopened_host;
+                        streams = reopened_streams;
+                    }
+                    None => return,
+                }
+            }
+            // Left over from streams that have since been rebuilt.
+            Ok(StreamEvent::Error(_, cpal::StreamError::DeviceNotAvailable)) => {}
+            Ok(StreamEvent::Error(_, err)) => eprintln!(" Stream error: {}", err),
+            Ok(StreamEvent::Stop) | Err(_) => return,
+        }
+    }
+}
+
+/// Reopens the devices until they come back or the policy gives up.
+#[allow(clippy::too_many_arguments)]
+fn reconnect(
+    host: &StreamHost,
+    reclaimed: &Receiver<Box<dyn AudioProcessor>>,
+    home: &Sender<Box<dyn AudioProcessor>>,
+    ring: RingConfig,
+    shared: &StreamShared,
+    generation: u32,
+    events: &Sender<StreamEvent>,
+    event_rx: &Receiver<StreamEvent>,
+) -> Option<(StreamHost, Streams)> {
+    let policy = host.reconnect;
+    let mut attempt = 0;
+    loop {
+        // The processor comes back once the old output callback is dropped.
+        let Ok(processor) = reclaimed.recv_timeout(RECLAIM_TIMEOUT) else {
+            StreamShared::set(&shared.status, StreamStatus::Disconnected);
+            return None;
+        };
+        if policy.max_attempts.is_some_and(|max| attempt >= max) {
+            StreamShared::set(&shared.status, StreamStatus::Disconnected);
+            return None;
+        }
+        attempt += 1;
+        StreamShared::set(&shared.status, StreamStatus::Reconnecting { attempt });
+
+        // Wait before retrying, but stop straight away if asked to.
+        match event_rx.recv_timeout(policy.retry_interval) {
+            Ok(StreamEvent::Stop) | Err(RecvTimeoutError::Disconnected) => return None,
+            Ok(StreamEvent::Error(..)) | Err(RecvTimeoutError::Timeout) => {}
+        }
+
+        let reclaim = Reclaim {
+            processor: Some(processor),
+            home: home.clone(),
+        };
+        let reopened = host.reopen();
+        let opened = reopened.and_then(|reopened| {
+            let streams = reopened.open(reclaim, ring, shared, generation, events)?;
+            Ok((reopened, streams))
+        });
+        if let Ok(opened) = opened {
+            return Some(opened);
+        }
+    }
+}
+
+impl RunningStreams {
+    /// Underrun and overrun counters of the buffer between the streams.
+    /// They start again from zero if the streams are rebuilt.
+    pub fn stats(&self) -> Arc<RingStats> {
+        StreamShared::get(&self.shared.stats)
+    }
+
+    /// Measured time from capture to playback through the ring buffer,
+    /// excluding the latency of the processors themselves.
+    pub fn latency(&self) -> Option<Duration> {
+        StreamShared::get(&self.shared.latency).latency()
+    }
+
+    /// Delay the processor reported when the streams started.
+    pub fn processor_latency(&self) -> Duration {
+        StreamShared::get(&self.shared.processor_latency)
+    }
+
+    pub fn status(&self) -> StreamStatus {
+        StreamShared::get(&self.shared.status)
+    }
+}
+
+impl Drop for RunningStreams {
+    fn drop(&mut self) {
+        let _ = self.events.send(StreamEvent::Stop);
+        if let Some(supervisor) = self.supervisor.take() {
+            let _ = supervisor.join();
+        }
+    }
+}
+
+/// A device that is gone before it has been opened is missing rather than
+/// disconnected.
+fn not_available(err: AudioError, device: &cpal::Device) -> AudioError {
+    match err {
+        AudioError::DeviceDisconnected => AudioError::DeviceNotFound(format!(
+            "Device \"{}\" is not available",
+            device.name().unwrap_or_default()
+        )),
+        err => err,
+    }
+}
+
+/// Forwards stream errors to the supervisor, tagged with the build they
+/// came from.
+fn error_callback(
+    events: &Sender<StreamEvent>,
+    generation: u32,
+) -> impl FnMut(cpal::StreamError) + Send + 'static {
+    let events = events.clone();
+    move |err| {
+        let _ = events.send(StreamEvent::Error(generation, err));
+    }
+}
+
+/// Opens a capture stream of sample type `T`, converting each block to f32
+/// and handing it on in pieces of at most [`MAX_BLOCK_FRAMES`] frames.
+fn build_input<T>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    mut on_data: impl FnMut(&[f32], Option<Instant>) + Send + 'static,
+    on_error: impl FnMut(cpal::StreamError) + Send + 'static,
+) -> Result<cpal::Stream, AudioError>
+where
+    T: SizedSample,
+    f32: FromSample<T>,
+{
+    let channels = config.channels as usize;
+    let sample_rate = config.sample_rate.0;
+    let mut converted = vec![0.0; MAX_BLOCK_FRAMES * channels];
+    let stream = device.build_input_stream(
+        config,
+        move |data: &[T], info: &cpal::InputCallbackInfo| {
+            let timestamp = info.timestamp();
+            let capture = system_time(timestamp.capture, timestamp.callback);
+            for (index, chunk) in data.chunks(converted.len()).enumerate() {
+                let converted = &mut converted[..chunk.len()];
+                for (sample_out, &sample) in converted.iter_mut().zip(chunk) {
+                    *sample_out = sample.to_sample::<f32>();
+                }
+                on_data(
+                    converted,
+                    capture.and_then(|t| t.checked_add(chunk_offset(index, sample_rate))),
+                );
+            }
+        },
+        on_error,
+        None,
+    )?;
+    Ok(stream)
+}
+
+/// Opens a playback stream of sample type `T`, rendering each block in f32
+/// in pieces of at most [`MAX_BLOCK_FRAMES`] frames.
+fn build_output<T>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    mut dither: Dither,
+    mut render: impl FnMut(&mut [f32], Option<Instant>) + Send + 'static,
+    on_error: impl FnMut(cpal::StreamError) + Send + 'static,
+) -> Result<cpal::Stream, AudioError>
+where
+    T: SizedSample + FromSample<f32>,
+{
+    let channels = config.channels as usize;
+    let sample_rate = config.sample_rate.0;
+    let mut block = vec![0.0; MAX_BLOCK_FRAMES * channels];
+    let stream = device.build_output_stream(
+        config,
+        move |output: &mut [T], info: &cpal::OutputCallbackInfo| {
+            let timestamp = info.timestamp();
+            let playback = system_time(timestamp.playback, timestamp.callback);
+            for (index, chunk) in output.chunks_mut(block.len()).enumerate() {
+                let block = &mut block[..chunk.len()];
+                render(
+                    block,
+                    playback.and_then(|t| t.checked_add(chunk_offset(index, sample_rate))),
+                );
+                for (sample_out, &sample) in chunk.iter_mut().zip(block.iter()) {
+                    *sample_out = dither.apply(sample).to_sample::<T>();
+                }
+            }
+        },
+        on_error,
+        None,
+    )?;
+    Ok(stream)
+}
+
+/// Places a stream timestamp on the system clock, using the callback's own
+/// timestamp as the point that corresponds to now.
+fn system_time(instant: cpal::StreamInstant, callback: cpal::StreamInstant) -> Option<Instant> {
+    let now = Instant::now();
+    match instant.duration_since(&callback) {
+        Some(after) => now.checked_add(after),
+        None => now.checked_sub(callback.duration_since(&instant)?),
+    }
+}
+
+/// Time from the start of a callback to its `index`th piece.
+fn chunk_offset(index: usize, sample_rate: u32) -> Duration {
+    Duration::from_secs_f64((index * MAX_BLOCK_FRAMES) as f64 / sample_rate as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mix::{MIX, OUTPUT_GAIN};
+    use crate::pitch_shifter::PITCH_FACTOR;
+    use crate::{
+        AutoTune, DryWet, EffectChain, Harmonizer, HarmonizerSettings, Interval, Limiter, Param,
+        PerChannel, PitchShifter, PitchTracker, Voice,
+    };
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+    use std::f32::consts::PI;
+    use std::sync::Barrier;
+
+    /// Counts allocations and frees made by each thread, so tests running in
+    /// parallel don't see each other's.
+    struct CountingAllocator;
+
+    thread_local! {
+        static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    fn count_allocation() {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+    }
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            count_allocation();
+            unsafe { System.alloc(layout) }
+        }
+
+        unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+            count_allocation();
+            unsafe { System.alloc_zeroed(layout) }
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            count_allocation();
+            unsafe { System.realloc(ptr, layout, new_size) }
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            count_allocation();
+            unsafe { System.dealloc(ptr, layout) }
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    const INPUT_RATE: u32 = 44_100;
+    const OUTPUT_RATE: u32 = 48_000;
+
+    /// Runs the capture and playback callbacks for `blocks` 10 ms blocks of
+    /// a sung tone on another thread, returning them with the number of
+    /// allocations and frees made after a warm-up. With `edits`, the thread waits on it
+    /// twice once the warm-up is over so the caller can edit the chain.
+    fn run_callbacks(
+        mut capture: Capture,
+        mut playback: Playback,
+        blocks: usize,
+        edits: Option<Arc<Barrier>>,
+    ) -> Receiver<(Capture, Playback, usize)> {
+        let (done, result) = mpsc::channel();
+        thread::spawn(move || {
+            let mut input = vec![0.0; INPUT_RATE as usize / 100];
+            let mut output = vec![0.0; 2 * OUTPUT_RATE as usize / 100];
+            let mut phase = 0.0_f32;
+            let mut allocations = 0;
+            for block in 0..2 * blocks {
+                if block == blocks {
+                    allocations = ALLOCATIONS.with(Cell::get);
+                    if let Some(edits) = &edits {
+                        edits.wait();
+                        edits.wait();
+                    }
+                }
+                for sample in &mut input {
+                    *sample = 0.5 * phase.sin() + 0.2 * (2.0 * phase).sin();
+                    phase = (phase + 2.0 * PI * 220.0 / INPUT_RATE as f32) % (2.0 * PI);
+                }
+                capture.write(&input, None);
+                playback.render(&mut output, None);
+            }
+            let allocations = ALLOCATIONS.with(Cell::get) - allocations;
+            let _ = done.send((capture, playback, allocations));
+        });
+        result
+    }
+
+    #[test]
+    fn callbacks_neither_allocate_nor_block() {
+        let pitch_factor = Param::new(PITCH_FACTOR.with_default(1.5));
+        let shifted_factor = Param::new(PITCH_FACTOR);
+        let autotune = AutoTune::new(pitch_factor, shifted_factor.clone());
+        let autotune_settings = autotune.settings();
+        let harmonizer = Harmonizer::new(Default::default()).with_settings(HarmonizerSettings {
+            voices: [
+                Some(Voice::new(Interval::Diatonic(2))),
+                Some(Voice::new(Interval::Semitones(-12.0))),
+                None,
+                None,
+            ],
+            ..HarmonizerSettings::default()
+        });
+        let harmony_settings = harmonizer.settings();
+        let shifter = { PerChannel::new(move || PitchShifter::new(shifted_factor.clone())) };
+        let mut chain = EffectChain::new();
+        chain.push("tuner", PitchTracker::new());
+        chain.push("autotune", autotune);
+        chain.push(
+            "pitch",
+            DryWet::new(shifter, Param::new(MIX.with_default(0.8))),
+        );
+        chain.push("harmony", harmonizer);
+        chain.push(
+            "limiter",
+            Limiter::new(Param::new(OUTPUT_GAIN.with_default(3.0))),
+        );
+        let mut remote = chain.remote();
+
+        let mut processor: Box<dyn AudioProcessor> = Box::new(chain);
+        processor.prepare(OUTPUT_RATE, 2);
+        let (home, _reclaimed) = mpsc::channel();
+        let (producer, consumer) = ring_buffer(RingConfig {
+            channels: 2,
+            ..RingConfig::default()
+        });
+        let latency = Arc::new(LatencyMonitor::new(OUTPUT_RATE));
+        let capture = Capture::new(
+            producer,
+            ChannelMatrix::default_for(1, 2),
+            INPUT_RATE,
+            OUTPUT_RATE,
+            Arc::clone(&latency),
+        );
+        let playback = Playback {
+            consumer,
+            reclaim: Reclaim {
+                processor: Some(processor),
+                home,
+            },
+            latency,
+        };
+
+        let (capture, playback, allocations) =
+            run_callbacks(capture, playback, 100, None).recv().unwrap();
+        assert_eq!(allocations, 0);
+
+        // Edit the chain once the callbacks are being measured, so the edits
+        // are applied on the audio thread while it counts.
+        let edits = Arc::new(Barrier::new(2));
+        let result = run_callbacks(capture, playback, 20, Some(Arc::clone(&edits)));
+        edits.wait();
+        remote
+            .insert(0, "trim", Limiter::new(Param::new(OUTPUT_GAIN)))
+            .unwrap();
+        remote.move_effect(4, 2).unwrap();
+        remote.set_bypassed(3, true).unwrap();
+        remote.remove(1).unwrap();
+        edits.wait();
+        let (capture, playback, allocations) = result.recv().unwrap();
+        assert_eq!(allocations, 0);
+        assert_eq!(
+            remote.names().collect::<Vec<_>>(),
+            ["trim", "harmony", "autotune", "pitch", "limiter"]
+        );
+
+        // With the settings held by this thread, a callback that waited for
+        // a lock would never finish.
+        let _locks = (
+            autotune_settings.lock().unwrap(),
+            harmony_settings.lock().unwrap(),
+        );
+        let (_, _, allocations) = run_callbacks(capture, playback, 20, None)
+            .recv_timeout(Duration::from_secs(60))
+            .expect("callback blocked on a lock");
+        assert_eq!(allocations, 0);
+    }
+}
@@ -0,0 +1,223 @@
+use anyhow::Context;
+use audio_effects::control::{Controls, PitchCommand, PitchControl};
+use audio_effects::{
+    AudioError, Param, RunningStreams, SharedLevels, SharedPitch, StreamStatus, ratio_to_semitones,
+};
+use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::Frame;
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Style, Stylize};
+use ratatui::symbols::Marker;
+use ratatui::text::Line;
+use ratatui::widgets::{Axis, Block, Chart, Dataset, Gauge, GraphType, Paragraph};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Time between redraws, also how long a key press can go unnoticed.
+const FRAME_INTERVAL: Duration = Duration::from_millis(50);
+/// Quietest level the meters show.
+const METER_FLOOR_DB: f32 = -60.0;
+/// Captured samples shown in the waveform, about 40 ms at 48 kHz.
+const WAVEFORM_SAMPLES: usize = 2048;
+/// Pitch change per press of up or down, in semitones.
+const COARSE_STEP: f32 = 1.0;
+/// Pitch change per press of left or right, in semitones.
+const FINE_STEP: f32 = 0.1;
+
+/// What the terminal UI shows and controls while the streams run.
+pub struct Live<'a> {
+    pub streams: &'a RunningStreams,
+    pub control: &'a PitchControl,
+    pub controls: &'a mut Controls,
+    /// Pitch factor after auto-tune, as the shifters see it.
+    pub shifted_factor: Param,
+    pub input_pitch: Arc<SharedPitch>,
+    pub input_levels: Arc<SharedLevels>,
+    pub output_levels: Arc<SharedLevels>,
+}
+
+/// Runs the full-screen interface until q or Esc is pressed.
+pub fn run(mut live: Live) -> Result<(), Box<dyn std::error::Error>> {
+    let mut terminal = ratatui::init();
+    let result = event_loop(&mut terminal, &mut live);
+    ratatui::restore();
+    if result.is_ok() {
+        let stats = live.streams.stats();
+        println!(
+            " Exiting... ({} underruns, {} overruns)",
+            stats.underruns(),
+            stats.overruns()
+        );
+    }
+    result
+}
+
+fn event_loop(
+    terminal: &mut ratatui::DefaultTerminal,
+    live: &mut Live,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut waveform = vec![0.0; WAVEFORM_SAMPLES];
+    let mut message = String::new();
+    loop {
+        if live.streams.status() == StreamStatus::Disconnected {
+            return Err(AudioError::DeviceDisconnected.into());
+        }
+        // Show changes made from anywhere, such as a loaded preset.
+        if let Some(param) = live.controls.params.changes().last() {
+            message = param.to_string();
+        }
+        live.input_levels.scope(&mut waveform);
+        terminal.draw(|frame| draw(frame, live, &waveform, &message))?;
+
+        if !event::poll(FRAME_INTERVAL)? {
+            continue;
+        }
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        let command = match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+            KeyCode::Up => PitchCommand::Nudge(COARSE_STEP),
+            KeyCode::Down => PitchCommand::Nudge(-COARSE_STEP),
+            KeyCode::Right => PitchCommand::Nudge(FINE_STEP),
+            KeyCode::Left => PitchCommand::Nudge(-FINE_STEP),
+            KeyCode::Char('0') => PitchCommand::Set(0.0),
+            KeyCode::Char(' ') => {
+                message = match toggle_bypass(live.controls) {
+                    Ok(true) => "Pitch shifter bypassed".to_string(),
+                    Ok(false) => "Pitch shifter active".to_string(),
+                    Err(err) => err.to_string(),
+                };
+                continue;
+            }
+            _ => continue,
+        };
+        if let Err(err) = live.control.apply(command) {
+            message = err.to_string();
+        }
+    }
+}
+
+/// Flips the pitch shifter's bypass and returns whether it is now bypassed.
+pub fn toggle_bypass(controls: &mut Controls) -> anyhow::Result<bool> {
+    let effects = &mut controls.effects;
+    let index = effects
+        .position("pitch")
+        .context("No pitch shifter in the effect chain")?;
+    let bypassed = !effects.is_bypassed(index);
+    effects.set_bypassed(index, bypassed)?;
+    Ok(bypassed)
+}
+
+fn draw(frame: &mut Frame, live: &Live, waveform: &[f32], message: &str) {
+    let [status, input, output, scope, help] = Layout::vertical([
+        Constraint::Length(6),
+        Constraint::Length(3),
+        Constraint::Length(3),
+        Constraint::Min(5),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+
+    frame.render_widget(status_text(live, message), status);
+    frame.render_widget(meter("Input", &live.input_levels), input);
+    frame.render_widget(meter("Output", &live.output_levels), output);
+    draw_waveform(frame, scope, waveform);
+    frame.render_widget(
+        Line::from(" ↑↓ pitch ±1 st   ←→ ±10 cents   0 reset   space bypass   q quit").dim(),
+        help,
+    );
+}
+
+fn status_text<'a>(live: &Live, message: &'a str) -> Paragraph<'a> {
+    let bypassed = live
+        .controls
+        .effects
+        .position("pitch")
+        .is_some_and(|index| live.controls.effects.is_bypassed(index));
+    let pitch = format!(
+        " Pitch {:+.2} st (ratio {:.3}), applied {:+.2} st{}",
+        live.control.semitones(),
+        live.control.pitch_factor(),
+        ratio_to_semitones(live.shifted_factor.get()),
+        if bypassed { "  [BYPASSED]" } else { "" }
+    );
+    let input = match live.input_pitch.get() {
+        Some(pitch) => format!(
+            " Input {} {:+.0}c ({:.1} Hz)",
+            pitch.note(),
+            pitch.cents(),
+            pitch.frequency
+        ),
+        None => " Input --".to_string(),
+    };
+
+    let stats = live.streams.stats();
+    let latency = live.streams.latency().map_or("--".to_string(), |latency| {
+        format!("{:.1} ms", latency.as_secs_f32() * 1000.0)
+    });
+    let mut streams = format!(
+        " Underruns {}  Overruns {}  Latency {} (+{:.1} ms processing)",
+        stats.underruns(),
+        stats.overruns(),
+        latency,
+        live.streams.processor_latency().as_secs_f32() * 1000.0
+    );
+    if let StreamStatus::Reconnecting { attempt } = live.streams.status() {
+        streams += &format!("  Waiting for the audio device (attempt {attempt})");
+    }
+
+    Paragraph::new(vec![
+        Line::from(pitch).bold(),
+        Line::from(input),
+        Line::from(streams),
+        Line::from(format!(" {message}")).yellow(),
+    ])
+    .block(Block::bordered().title(" audio_effects "))
+}
+
+/// Peak gauge on a dB scale, labelled with peak and RMS.
+fn meter<'a>(title: &'a str, levels: &SharedLevels) -> Gauge<'a> {
+    let (peak, rms) = (to_db(levels.peak()), to_db(levels.rms()));
+    let color = match peak {
+        db if db >= -1.0 => Color::Red,
+        db if db >= -12.0 => Color::Yellow,
+        _ => Color::Green,
+    };
+    Gauge::default()
+        .block(Block::bordered().title(format!(" {title} ")))
+        .gauge_style(Style::new().fg(color))
+        .ratio(meter_ratio(peak))
+        .label(format!("peak {peak:.1} dB  rms {rms:.1} dB"))
+}
+
+fn draw_waveform(frame: &mut Frame, area: Rect, waveform: &[f32]) {
+    let points: Vec<(f64, f64)> = waveform
+        .iter()
+        .enumerate()
+        .map(|(i, &sample)| (i as f64, sample as f64))
+        .collect();
+    let dataset = Dataset::default()
+        .marker(Marker::Braille)
+        .graph_type(GraphType::Line)
+        .style(Style::new().cyan())
+        .data(&points);
+    let chart = Chart::new(vec![dataset])
+        .block(Block::bordered().title(" Captured input "))
+        .x_axis(Axis::default().bounds([0.0, waveform.len() as f64]))
+        .y_axis(Axis::default().bounds([-1.0, 1.0]));
+    frame.render_widget(chart, area);
+}
+
+/// Linear level to dB, bottoming out at the meter floor.
+fn to_db(level: f32) -> f32 {
+    (20.0 * level.log10()).max(METER_FLOOR_DB)
+}
+
+/// Share of a meter filled at `db`.
+fn meter_ratio(db: f32) -> f64 {
+    ((db - METER_FLOOR_DB) / -METER_FLOOR_DB).clamp(0.0, 1.0) as f64
+}
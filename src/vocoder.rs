@@ -0,0 +1,425 @@
+use rustfft::num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+/// Added to magnitudes before taking logs so silent bins stay finite.
+const ENVELOPE_FLOOR: f32 = 1e-6;
+/// Largest boost applied when reshaping a bin's envelope, so noise between
+/// formants isn't amplified without bound.
+const MAX_ENVELOPE_GAIN: f32 = 16.0;
+
+/// Spectral-envelope handling for formant-preserving shifts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Formants {
+    /// Cepstral coefficients kept when estimating the envelope; fewer
+    /// coefficients give a smoother envelope. Must stay below the pitch
+    /// period in samples so harmonics don't leak into it.
+    pub lifter: usize,
+    /// Ratio the envelope is moved by; 1.0 keeps formants where they were.
+    pub shift: f32,
+}
+
+/// Streaming STFT phase vocoder pitch shifter.
+///
+/// Pitch is changed by moving each spectral peak (with the bins around it)
+/// to `peak * pitch_factor` while keeping the hop size fixed, so the output
+/// has exactly as many samples as the input. Output is delayed by [`PhaseVocoder::latency`]
+/// samples.
+///
+/// With [`Formants`] set, the spectral envelope is estimated by cepstral
+/// smoothing, divided out before shifting and reapplied afterwards, so
+/// voices change pitch without changing timbre.
+pub struct PhaseVocoder {
+    frame_size: usize,
+    oversampling: usize,
+    hop: usize,
+    window: Vec<f32>,
+    fft: Arc<dyn Fft<f32>>,
+    ifft: Arc<dyn Fft<f32>>,
+    spectrum: Vec<Complex<f32>>,
+    scratch: Vec<Complex<f32>>,
+    in_fifo: Vec<f32>,
+    out_fifo: Vec<f32>,
+    output_accum: Vec<f32>,
+    analysis: Vec<Complex<f32>>,
+    magnitude: Vec<f32>,
+    true_bin: Vec<f32>,
+    last_phase: Vec<f32>,
+    rotation: Vec<f32>,
+    last_rotation: Vec<f32>,
+    peaks: Vec<usize>,
+    rover: usize,
+    formants: Option<Formants>,
+    cepstrum: Vec<Complex<f32>>,
+    envelope: Vec<f32>,
+}
+
+impl PhaseVocoder {
+    /// Creates a vocoder with an FFT frame of `frame_size` samples and
+    /// `oversampling` overlapping frames per frame length (hop = frame / oversampling).
+    pub fn new(frame_size: usize, oversampling: usize) -> Self {
+        assert!(
+            frame_size.is_power_of_two(),
+            "frame size must be a power of two"
+        );
+        assert!(oversampling >= 4, "oversampling must be at least 4");
+        assert!(
+            frame_size.is_multiple_of(oversampling),
+            "frame size must be divisible by oversampling"
+        );
+
+        let mut planner = FftPlanner::new();
+        let fft = planner.plan_fft_forward(frame_size);
+        let ifft = planner.plan_fft_inverse(frame_size);
+        let scratch_len = fft
+            .get_inplace_scratch_len()
+            .max(ifft.get_inplace_scratch_len());
+
+        // Periodic Hann window, used for both analysis and synthesis.
+        let window = (0..frame_size)
+            .map(|n| 0.5 - 0.5 * (2.0 * PI * n as f32 / frame_size as f32).cos())
+            .collect();
+
+        let bins = frame_size / 2 + 1;
+        let hop = frame_size / oversampling;
+
+        Self {
+            frame_size,
+            oversampling,
+            hop,
+            window,
+            fft,
+            ifft,
+            spectrum: vec![Complex::new(0.0, 0.0); frame_size],
+            scratch: vec![Complex::new(0.0, 0.0); scratch_len],
+            in_fifo: vec![0.0; frame_size],
+            out_fifo: vec![0.0; hop],
+            output_accum: vec![0.0; frame_size],
+            analysis: vec![Complex::new(0.0, 0.0); bins],
+            magnitude: vec![0.0; bins],
+            true_bin: vec![0.0; bins],
+            last_phase: vec![0.0; bins],
+            rotation: vec![0.0; bins],
+            last_rotation: vec![0.0; bins],
+            peaks: Vec::with_capacity(bins),
+            rover: frame_size - hop,
+            formants: None,
+            cepstrum: vec![Complex::new(0.0, 0.0); frame_size],
+            envelope: vec![1.0; bins],
+        }
+    }
+
+    /// Delay in samples between an input sample and its shifted output.
+    pub fn latency(&self) -> usize {
+        self.frame_size
+    }
+
+    /// Enables formant preservation, or lets formants move with the pitch when `None`.
+    pub fn set_formants(&mut self, formants: Option<Formants>) {
+        if let Some(formants) = formants {
+            assert!(
+                formants.lifter > 0 && formants.lifter < self.frame_size / 2,
+                "lifter must be between 1 and half the frame size"
+            );
+        }
+        self.formants = formants;
+    }
+
+    pub fn formants(&self) -> Option<Formants> {
+        self.formants
+    }
+
+    /// Clears all internal state, as if no audio had been processed yet.
+    pub fn reset(&mut self) {
+        self.in_fifo.fill(0.0);
+        self.out_fifo.fill(0.0);
+        self.output_accum.fill(0.0);
+        self.last_phase.fill(0.0);
+        self.rotation.fill(0.0);
+        self.last_rotation.fill(0.0);
+        self.rover = self.frame_size - self.hop;
+    }
+
+    /// Shifts `input` by `pitch_factor` into `output`, which must have the same length.
+    pub fn process(&mut self, input: &[f32], output: &mut [f32], pitch_factor: f32) {
+        assert_eq!(input.len(), output.len(), "input and output lengths differ");
+        for (sample_in, sample_out) in input.iter().zip(output.iter_mut()) {
+            *sample_out = self.process_sample(*sample_in, pitch_factor);
+        }
+    }
+
+    /// Shifts `block` by `pitch_factor` in place.
+    pub fn process_in_place(&mut self, block: &mut [f32], pitch_factor: f32) {
+        for sample in block.iter_mut() {
+            *sample = self.process_sample(*sample, pitch_factor);
+        }
+    }
+
+    fn process_sample(&mut self, sample: f32, pitch_factor: f32) -> f32 {
+        // The newest hop of the input frame lines up with the hop being played out.
+        let start = self.frame_size - self.hop;
+        self.in_fifo[self.rover] = sample;
+        let out = self.out_fifo[self.rover - start];
+        self.rover += 1;
+
+        if self.rover >= self.frame_size {
+            self.rover = start;
+            self.process_frame(pitch_factor);
+        }
+        out
+    }
+
+    fn process_frame(&mut self, pitch_factor: f32) {
+        let n = self.frame_size;
+        let bins = n / 2 + 1;
+        let hop = self.hop;
+        // Expected phase advance per hop for a sinusoid centred on bin 1.
+        let expected = 2.0 * PI / self.oversampling as f32;
+
+        // Analysis
+        for (i, bin) in self.spectrum.iter_mut().enumerate() {
+            *bin = Complex::new(self.in_fifo[i] * self.window[i], 0.0);
+        }
+        self.fft
+            .process_with_scratch(&mut self.spectrum, &mut self.scratch);
+        self.analysis.copy_from_slice(&self.spectrum[..bins]);
+
+        for k in 0..bins {
+            let (magn, phase) = self.analysis[k].to_polar();
+            // Unwrap the deviation from the bin's expected advance into [-pi, pi].
+            let delta = wrap_phase(phase - self.last_phase[k] - k as f32 * expected);
+            self.last_phase[k] = phase;
+            self.magnitude[k] = magn;
+            self.true_bin[k] = k as f32 + delta / expected;
+        }
+
+        if let Some(formants) = self.formants {
+            self.estimate_envelope(formants.lifter);
+        }
+
+        // Peak-locked shifting: every peak carries its whole region of
+        // influence to the shifted position, rotated so the phase keeps
+        // advancing at the shifted frequency.
+        self.peaks.clear();
+        for k in 1..bins - 1 {
+            if self.magnitude[k] > self.magnitude[k - 1]
+                && self.magnitude[k] >= self.magnitude[k + 1]
+            {
+                self.peaks.push(k);
+            }
+        }
+
+        self.spectrum.fill(Complex::new(0.0, 0.0));
+        for (i, &peak) in self.peaks.iter().enumerate() {
+            let lo = if i == 0 {
+                0
+            } else {
+                (self.peaks[i - 1] + peak).div_ceil(2)
+            };
+            let hi = match self.peaks.get(i + 1) {
+                Some(&next) => (peak + next).div_ceil(2),
+                None => bins,
+            };
+
+            let shift = (peak as f32 * pitch_factor).round() as isize - peak as isize;
+            let rotation = wrap_phase(
+                self.last_rotation[peak] + (pitch_factor - 1.0) * self.true_bin[peak] * expected,
+            );
+            let rotor = Complex::from_polar(1.0, rotation);
+
+            for k in lo..hi {
+                self.rotation[k] = rotation;
+                let target = k as isize + shift;
+                if (0..bins as isize).contains(&target) {
+                    // Swap the source bin's envelope for the one wanted at the target.
+                    let gain = match self.formants {
+                        Some(formants) => {
+                            let wanted = self.envelope_at(target as f32 / formants.shift);
+                            (wanted / self.envelope[k]).min(MAX_ENVELOPE_GAIN)
+                        }
+                        None => 1.0,
+                    };
+                    self.spectrum[target as usize] += self.analysis[k] * rotor * gain;
+                }
+            }
+        }
+        std::mem::swap(&mut self.rotation, &mut self.last_rotation);
+
+        // Synthesis: restore conjugate symmetry so the inverse transform is real.
+        for k in bins..n {
+            self.spectrum[k] = self.spectrum[n - k].conj();
+        }
+        self.ifft
+            .process_with_scratch(&mut self.spectrum, &mut self.scratch);
+
+        // Hann^2 overlap-added at this hop sums to 3/8 * oversampling.
+        let scale = 1.0 / (n as f32 * 0.375 * self.oversampling as f32);
+        for i in 0..n {
+            self.output_accum[i] += self.window[i] * self.spectrum[i].re * scale;
+        }
+
+        self.out_fifo.copy_from_slice(&self.output_accum[..hop]);
+        self.output_accum.copy_within(hop.., 0);
+        self.output_accum[n - hop..].fill(0.0);
+        self.in_fifo.copy_within(hop.., 0);
+    }
+
+    /// Smooths the log magnitude spectrum by keeping only the first `lifter`
+    /// cepstral coefficients, leaving the spectral envelope in `envelope`.
+    fn estimate_envelope(&mut self, lifter: usize) {
+        let n = self.frame_size;
+        let bins = n / 2 + 1;
+        for (k, bin) in self.cepstrum.iter_mut().enumerate() {
+            let magn = self.magnitude[if k < bins { k } else { n - k }];
+            *bin = Complex::new((magn + ENVELOPE_FLOOR).ln(), 0.0);
+        }
+        self.ifft
+            .process_with_scratch(&mut self.cepstrum, &mut self.scratch);
+
+        // The cepstrum of a real, even spectrum is symmetric; lifter both halves.
+        self.cepstrum[lifter..=n - lifter].fill(Complex::new(0.0, 0.0));
+        self.fft
+            .process_with_scratch(&mut self.cepstrum, &mut self.scratch);
+        for (envelope, bin) in self.envelope.iter_mut().zip(&self.cepstrum) {
+            *envelope = (bin.re / n as f32).exp();
+        }
+    }
+
+    /// Envelope at fractional bin `bin`, linearly interpolated; silent past Nyquist.
+    fn envelope_at(&self, bin: f32) -> f32 {
+        let last = self.envelope.len() - 1;
+        if bin >= last as f32 {
+            return ENVELOPE_FLOOR;
+        }
+        let index = bin.max(0.0) as usize;
+        let frac = bin.max(0.0) - index as f32;
+        self.envelope[index] + frac * (self.envelope[index + 1] - self.envelope[index])
+    }
+}
+
+/// Wraps a phase value into [-pi, pi].
+fn wrap_phase(phase: f32) -> f32 {
+    phase - 2.0 * PI * (phase / (2.0 * PI)).round()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f32 = 48_000.0;
+
+    fn sine(freq: f32, len: usize) -> Vec<f32> {
+        (0..len)
+            .map(|i| 0.5 * (2.0 * PI * freq * i as f32 / SAMPLE_RATE).sin())
+            .collect()
+    }
+
+    fn shift(input: &[f32], pitch_factor: f32) -> Vec<f32> {
+        let mut vocoder = PhaseVocoder::new(2048, 4);
+        let mut output = vec![0.0; input.len()];
+        for (block_in, block_out) in input.chunks(512).zip(output.chunks_mut(512)) {
+            vocoder.process(block_in, block_out, pitch_factor);
+        }
+        output
+    }
+
+    fn zero_crossing_freq(samples: &[f32]) -> f32 {
+        let rising = samples
+            .windows(2)
+            .filter(|w| w[0] <= 0.0 && w[1] > 0.0)
+            .count();
+        rising as f32 * SAMPLE_RATE / samples.len() as f32
+    }
+
+    fn rms(samples: &[f32]) -> f32 {
+        (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn unity_factor_is_a_delayed_copy() {
+        let input = sine(440.0, 16_384);
+        let output = shift(&input, 1.0);
+        let latency = PhaseVocoder::new(2048, 4).latency();
+
+        for i in 4096..input.len() {
+            assert!((output[i] - input[i - latency]).abs() < 1e-3, "sample {i}");
+        }
+    }
+
+    #[test]
+    fn shifts_frequency_and_keeps_length_and_level() {
+        let input = sine(440.0, 48_000);
+        for &(factor, expected) in &[(0.7, 308.0), (1.5, 660.0)] {
+            let output = shift(&input, factor);
+            assert_eq!(output.len(), input.len());
+
+            let tail = &output[8192..];
+            assert!((zero_crossing_freq(tail) - expected).abs() < 3.0);
+            assert!((rms(tail) / rms(&input) - 1.0).abs() < 0.2);
+        }
+    }
+
+    /// Harmonics of `f0` weighted by a single formant at 1200 Hz.
+    fn vowel(f0: f32, len: usize) -> Vec<f32> {
+        let mut samples = vec![0.0; len];
+        for harmonic in 1..(8000.0 / f0) as usize {
+            let freq = harmonic as f32 * f0;
+            let amplitude = 0.1 * (-((freq - 1200.0) / 400.0).powi(2)).exp();
+            for (i, sample) in samples.iter_mut().enumerate() {
+                *sample += amplitude * (2.0 * PI * freq * i as f32 / SAMPLE_RATE).sin();
+            }
+        }
+        samples
+    }
+
+    fn amplitude_at(samples: &[f32], freq: f32) -> f32 {
+        let (mut re, mut im) = (0.0, 0.0);
+        for (i, sample) in samples.iter().enumerate() {
+            let phase = 2.0 * PI * freq * i as f32 / SAMPLE_RATE;
+            re += sample * phase.cos();
+            im += sample * phase.sin();
+        }
+        2.0 * (re * re + im * im).sqrt() / samples.len() as f32
+    }
+
+    /// Frequency of the strongest harmonic of `f0`.
+    fn loudest_harmonic(samples: &[f32], f0: f32) -> f32 {
+        (1..20)
+            .map(|harmonic| harmonic as f32 * f0)
+            .max_by(|a, b| amplitude_at(samples, *a).total_cmp(&amplitude_at(samples, *b)))
+            .unwrap()
+    }
+
+    #[test]
+    fn formants_stay_put_or_follow_formant_shift() {
+        let input = vowel(200.0, 24_000);
+        // (pitch factor, formant setting, output f0, expected formant peak)
+        let cases = [
+            (1.5, None, 300.0, 1800.0),
+            (1.5, Some(1.0), 300.0, 1200.0),
+            (1.0, Some(1.5), 200.0, 1800.0),
+        ];
+        for (pitch_factor, shift, f0, expected) in cases {
+            let mut vocoder = PhaseVocoder::new(2048, 4);
+            vocoder.set_formants(shift.map(|shift| Formants { lifter: 96, shift }));
+            let mut output = input.clone();
+            for block in output.chunks_mut(512) {
+                vocoder.process_in_place(block, pitch_factor);
+            }
+            assert_eq!(loudest_harmonic(&output[8192..], f0), expected);
+        }
+    }
+
+    #[test]
+    fn reset_clears_buffered_audio() {
+        let mut vocoder = PhaseVocoder::new(2048, 4);
+        let mut block = sine(440.0, 4096);
+        vocoder.process_in_place(&mut block, 1.2);
+
+        vocoder.reset();
+        let mut silence = vec![0.0; 4096];
+        vocoder.process_in_place(&mut silence, 1.2);
+        assert!(silence.iter().all(|&s| s == 0.0));
+    }
+}
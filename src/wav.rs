@@ -0,0 +1,128 @@
+use anyhow::{Context, Result, bail};
+use hound::{SampleFormat, WavReader, WavSpec, WavWriter};
+use std::path::Path;
+
+/// Decoded audio as interleaved f32 samples in [-1, 1].
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioBuffer {
+    pub samples: Vec<f32>,
+    pub channels: u16,
+    pub sample_rate: u32,
+}
+
+impl AudioBuffer {
+    /// Number of frames (samples per channel).
+    pub fn frames(&self) -> usize {
+        self.samples.len() / self.channels as usize
+    }
+}
+
+/// Reads a 16/24-bit PCM or 32-bit float WAV file.
+///
+/// Returns the samples together with the file's spec so the result can be
+/// written back in the same format.
+pub fn read_wav(path: &Path) -> Result<(AudioBuffer, WavSpec)> {
+    let mut reader =
+        WavReader::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let spec = reader.spec();
+
+    let samples = match (spec.sample_format, spec.bits_per_sample) {
+        (SampleFormat::Float, 32) => reader.samples::<f32>().collect::<Result<Vec<_>, _>>()?,
+        (SampleFormat::Int, 16) => reader
+            .samples::<i16>()
+            .map(|s| s.map(|s| s as f32 / 32_768.0))
+            .collect::<Result<Vec<_>, _>>()?,
+        (SampleFormat::Int, 24) => reader
+            .samples::<i32>()
+            .map(|s| s.map(|s| s as f32 / 8_388_608.0))
+            .collect::<Result<Vec<_>, _>>()?,
+        (format, bits) => bail!("Unsupported WAV format: {bits}-bit {format:?}"),
+    };
+
+    let buffer = AudioBuffer {
+        samples,
+        channels: spec.channels,
+        sample_rate: spec.sample_rate,
+    };
+    Ok((buffer, spec))
+}
+
+/// Writes `buffer` using the sample format and bit depth from `spec`.
+///
+/// Integer formats are clipped to [-1, 1] before conversion.
+pub fn write_wav(path: &Path, buffer: &AudioBuffer, spec: WavSpec) -> Result<()> {
+    let spec = WavSpec {
+        channels: buffer.channels,
+        sample_rate: buffer.sample_rate,
+        ..spec
+    };
+    let mut writer = WavWriter::create(path, spec)
+        .with_context(|| format!("Failed to create {}", path.display()))?;
+
+    match (spec.sample_format, spec.bits_per_sample) {
+        (SampleFormat::Float, 32) => {
+            for &sample in &buffer.samples {
+                writer.write_sample(sample)?;
+            }
+        }
+        (SampleFormat::Int, 16) => {
+            for &sample in &buffer.samples {
+                writer.write_sample((sample.clamp(-1.0, 1.0) * 32_767.0).round() as i16)?;
+            }
+        }
+        (SampleFormat::Int, 24) => {
+            for &sample in &buffer.samples {
+                writer.write_sample((sample.clamp(-1.0, 1.0) * 8_388_607.0).round() as i32)?;
+            }
+        }
+        (format, bits) => bail!("Unsupported WAV format: {bits}-bit {format:?}"),
+    }
+
+    writer.finalize()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_supported_formats() {
+        let buffer = AudioBuffer {
+            samples: vec![0.0, 0.5, -0.5, 0.25, -1.0, 0.75],
+            channels: 2,
+            sample_rate: 44_100,
+        };
+        let formats = [
+            (SampleFormat::Int, 16, 1e-4),
+            (SampleFormat::Int, 24, 1e-6),
+            (SampleFormat::Float, 32, 0.0),
+        ];
+
+        for (sample_format, bits_per_sample, tolerance) in formats {
+            let spec = WavSpec {
+                channels: 2,
+                sample_rate: 44_100,
+                bits_per_sample,
+                sample_format,
+            };
+            let path = std::env::temp_dir().join(format!(
+                "audio_effects_round_trip_{bits_per_sample}_{}.wav",
+                std::process::id()
+            ));
+
+            write_wav(&path, &buffer, spec).unwrap();
+            let (read, read_spec) = read_wav(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+
+            assert_eq!(read_spec, spec);
+            assert_eq!(read.frames(), 3);
+            for (a, b) in read.samples.iter().zip(&buffer.samples) {
+                assert!(
+                    (a - b).abs() <= tolerance,
+                    "{bits_per_sample}-bit: {a} vs {b}"
+                );
+            }
+        }
+    }
+}
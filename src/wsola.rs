@@ -0,0 +1,405 @@
+use crate::params::Param;
+use crate::pitch_shifter::{DEFAULT_GLIDE, PITCH_FACTOR, ratio_to_semitones, semitones_to_ratio};
+use crate::processor::AudioProcessor;
+use crate::resample;
+use crate::smoothing::{Ramp, SmoothedParam};
+use std::f32::consts::PI;
+
+/// Length of each overlap-added grain.
+const GRAIN_SECONDS: f32 = 0.02;
+/// How far a grain may move from its nominal position to line up with the
+/// previous one.
+const SEARCH_SECONDS: f32 = 0.005;
+/// Shortest and longest duration changes [`stretch`] accepts.
+pub const TIME_RATIO_RANGE: (f32, f32) = (1.0 / 16.0, 16.0);
+/// Samples processed between updates of the smoothed pitch.
+const SMOOTHING_BLOCK: usize = 32;
+/// Rate assumed until [`AudioProcessor::prepare`] is called.
+const DEFAULT_SAMPLE_RATE: u32 = 48_000;
+
+/// Grain and search sizes in frames at `sample_rate`.
+fn sizes(sample_rate: u32) -> (usize, usize) {
+    let grain = (sample_rate as f32 * GRAIN_SECONDS) as usize & !1;
+    let search = (sample_rate as f32 * SEARCH_SECONDS) as usize;
+    (grain, search)
+}
+
+/// Periodic Hann window; copies spaced half a window apart sum to one.
+fn hann(len: usize) -> Vec<f32> {
+    (0..len)
+        .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / len as f32).cos())
+        .collect()
+}
+
+/// Offset into `candidates` where `reference` matches best, by normalised
+/// cross-correlation. `candidates` must be longer than `reference`.
+fn best_offset(reference: &[f32], candidates: &[f32]) -> usize {
+    let len = reference.len();
+    let mut energy: f32 = candidates[..len].iter().map(|s| s * s).sum();
+    let mut best = (0, f32::MIN);
+    for offset in 0..=candidates.len() - len {
+        if offset > 0 {
+            energy += candidates[offset + len - 1].powi(2) - candidates[offset - 1].powi(2);
+        }
+        let correlation: f32 = reference
+            .iter()
+            .zip(&candidates[offset..])
+            .map(|(a, b)| a * b)
+            .sum();
+        let score = correlation / energy.max(1e-9).sqrt();
+        if score > best.1 {
+            best = (offset, score);
+        }
+    }
+    best.0
+}
+
+/// Changes the duration of interleaved audio by `time_ratio` and its pitch
+/// by `pitch_ratio` independently, using waveform-similarity overlap-add.
+///
+/// A `time_ratio` of 2.0 makes the result twice as long; a `pitch_ratio`
+/// has the same meaning as [`PitchShifter`](crate::PitchShifter)'s factor.
+/// The audio is stretched by both ratios, then resampled back by the pitch
+/// ratio. Every channel uses the same grain positions, so stereo images
+/// stay intact.
+///
+/// # Panics
+///
+/// If `time_ratio` is outside [`TIME_RATIO_RANGE`] or `pitch_ratio` outside
+/// the range of [`PITCH_FACTOR`].
+pub fn stretch(
+    samples: &[f32],
+    channels: usize,
+    sample_rate: u32,
+    time_ratio: f32,
+    pitch_ratio: f32,
+) -> Vec<f32> {
+    assert!(
+        (TIME_RATIO_RANGE.0..=TIME_RATIO_RANGE.1).contains(&time_ratio),
+        "time ratio out of range"
+    );
+    assert!(
+        (PITCH_FACTOR.min..=PITCH_FACTOR.max).contains(&pitch_ratio),
+        "pitch ratio out of range"
+    );
+    let frames = samples.len() / channels;
+    let (grain, search) = sizes(sample_rate);
+    let half = grain / 2;
+    let window = hann(grain);
+    let total = time_ratio as f64 * pitch_ratio as f64;
+
+    // Pad so the first grain is centred on frame 0 and every grain has
+    // input to read and search through.
+    let lead = half + search;
+    let padded_frames = lead + frames + grain + 2 * search;
+    let mut padded = vec![0.0; padded_frames * channels];
+    padded[lead * channels..(lead + frames) * channels].copy_from_slice(samples);
+    let mono: Vec<f32> = padded
+        .chunks_exact(channels)
+        .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+        .collect();
+
+    let stretched_frames = (frames as f64 * total).round() as usize;
+    let mut output = vec![0.0; (stretched_frames + 2 * grain) * channels];
+    let analysis_hop = half as f64 / total;
+    let mut previous: Option<usize> = None;
+
+    for k in 0.. {
+        let target = k * half;
+        if target > stretched_frames + half {
+            break;
+        }
+        let nominal = search + (k as f64 * analysis_hop).round() as usize;
+        if nominal + grain + search > padded_frames {
+            break;
+        }
+
+        // Continue the previous grain as naturally as the input allows.
+        let start = match previous {
+            Some(previous) => {
+                let reference = &mono[previous + half..previous + grain];
+                let candidates = &mono[nominal - search..nominal + search + half];
+                nominal - search + best_offset(reference, candidates)
+            }
+            None => nominal,
+        };
+        previous = Some(start);
+
+        for (i, w) in window.iter().enumerate() {
+            let source = &padded[(start + i) * channels..(start + i + 1) * channels];
+            let out = &mut output[(target + i) * channels..(target + i + 1) * channels];
+            for (out, sample) in out.iter_mut().zip(source) {
+                *out += w * sample;
+            }
+        }
+    }
+
+    output.drain(..half * channels);
+    output.truncate(stretched_frames * channels);
+    if pitch_ratio == 1.0 {
+        return output;
+    }
+
+    // Play the stretched audio back faster or slower to restore the duration.
+    let source_rate = (sample_rate as f64 * pitch_ratio as f64).round() as u32;
+    let mut output = resample::resample(&output, channels, source_rate, sample_rate);
+    output.resize(
+        (frames as f64 * time_ratio as f64).round() as usize * channels,
+        0.0,
+    );
+    output
+}
+
+/// An overlap-added grain being played by [`WsolaShifter`].
+#[derive(Debug, Clone, Copy)]
+struct Grain {
+    // Source position read halfway through the grain.
+    centre: f64,
+    ratio: f64,
+    age: usize,
+}
+
+/// Live WSOLA pitch shifter.
+///
+/// Grains are read from a delay line at the pitch factor's speed; each new
+/// grain is nudged within a few milliseconds so its start matches what the
+/// previous grain is playing, which keeps splices smooth on periodic input.
+/// Processes a single channel. Latency grows with the widest shift the pitch
+/// factor's range allows: about 45 ms for two octaves either way.
+pub struct WsolaShifter {
+    pitch_factor: Param,
+    semitones: SmoothedParam,
+    // Largest factor either way that the pitch factor's range allows.
+    max_ratio: f32,
+    sample_rate: u32,
+    grain: usize,
+    search: usize,
+    delay: usize,
+    window: Vec<f32>,
+    history: Vec<f32>,
+    mask: usize,
+    written: usize,
+    grains: [Option<Grain>; 2],
+    reference: Vec<f32>,
+    candidates: Vec<f32>,
+}
+
+impl WsolaShifter {
+    /// Creates a shifter that reads its factor from `pitch_factor` on every block.
+    pub fn new(pitch_factor: Param) -> Self {
+        let semitones = ratio_to_semitones(pitch_factor.get());
+        let info = pitch_factor.info();
+        let max_ratio = info.max.max(1.0 / info.min);
+        let mut shifter = Self {
+            pitch_factor,
+            semitones: SmoothedParam::new(semitones, DEFAULT_GLIDE, DEFAULT_SAMPLE_RATE),
+            max_ratio,
+            sample_rate: 0,
+            grain: 0,
+            search: 0,
+            delay: 0,
+            window: Vec::new(),
+            history: Vec::new(),
+            mask: 0,
+            written: 0,
+            grains: [None; 2],
+            reference: Vec::new(),
+            candidates: Vec::new(),
+        };
+        shifter.allocate(DEFAULT_SAMPLE_RATE);
+        shifter
+    }
+
+    /// Sets how pitch changes glide from one value to the next.
+    pub fn with_ramp(mut self, ramp: Ramp) -> Self {
+        self.semitones.set_ramp(ramp);
+        self
+    }
+
+    fn allocate(&mut self, sample_rate: u32) {
+        let (grain, search) = sizes(sample_rate);
+        self.sample_rate = sample_rate;
+        self.grain = grain;
+        self.search = search;
+        // Far enough behind the input for the fastest grain to finish reading.
+        let fastest = (self.max_ratio * grain as f32 / 2.0).ceil() as usize;
+        self.delay = fastest.max(grain) + search;
+        self.window = hann(grain);
+        let len = (2 * (self.delay + grain)).next_power_of_two();
+        self.history = vec![0.0; len];
+        self.mask = len - 1;
+        self.reference = vec![0.0; grain / 4];
+        self.candidates = vec![0.0; grain / 4 + 2 * search];
+        self.reset();
+    }
+
+    /// Linearly interpolated input at absolute position `position`.
+    fn read(&self, position: f64) -> f32 {
+        if position < 0.0 {
+            return 0.0;
+        }
+        let index = position as usize;
+        let frac = (position - index as f64) as f32;
+        let a = self.history[index & self.mask];
+        let b = self.history[(index + 1) & self.mask];
+        a + frac * (b - a)
+    }
+
+    /// Starts a grain at the current position, aligned with the previous one.
+    fn start_grain(&mut self, ratio: f64) {
+        let half = (self.grain / 2) as f64;
+        let now = self.written as f64;
+        let mut centre = now + half - self.delay as f64;
+
+        let previous = self.grains.iter().flatten().max_by_key(|grain| grain.age);
+        if let Some(previous) = previous.filter(|_| centre - ratio * half > self.search as f64) {
+            // The previous grain plays from its centre onwards while this one
+            // fades in; compare those source regions at input resolution.
+            let from = previous.centre;
+            let nominal = centre - ratio * half - self.search as f64;
+            let mut reference = std::mem::take(&mut self.reference);
+            let mut candidates = std::mem::take(&mut self.candidates);
+            for (i, sample) in reference.iter_mut().enumerate() {
+                *sample = self.read(from + i as f64);
+            }
+            for (i, sample) in candidates.iter_mut().enumerate() {
+                *sample = self.read(nominal + i as f64);
+            }
+            let offset = best_offset(&reference, &candidates) as f64;
+            centre += offset - self.search as f64;
+            self.reference = reference;
+            self.candidates = candidates;
+        }
+
+        let grain = Grain {
+            centre,
+            ratio,
+            age: 0,
+        };
+        // Replace whichever slot is free or finished.
+        match self.grains.iter_mut().find(|slot| slot.is_none()) {
+            Some(slot) => *slot = Some(grain),
+            None => {
+                let oldest = self
+                    .grains
+                    .iter_mut()
+                    .max_by_key(|g| g.unwrap().age)
+                    .unwrap();
+                *oldest = Some(grain);
+            }
+        }
+    }
+}
+
+impl AudioProcessor for WsolaShifter {
+    fn prepare(&mut self, sample_rate: u32, channels: usize) {
+        assert_eq!(channels, 1, "WsolaShifter processes a single channel");
+        self.semitones.set_sample_rate(sample_rate);
+        if sample_rate != self.sample_rate {
+            self.allocate(sample_rate);
+        }
+    }
+
+    fn process(&mut self, block: &mut [f32]) {
+        let target = ratio_to_semitones(self.pitch_factor.get());
+        if target != self.semitones.target() {
+            self.semitones.set_target(target);
+        }
+
+        let half = self.grain / 2;
+        for chunk in block.chunks_mut(SMOOTHING_BLOCK) {
+            let ratio = semitones_to_ratio(self.semitones.skip(chunk.len()))
+                .clamp(1.0 / self.max_ratio, self.max_ratio) as f64;
+            for sample in chunk.iter_mut() {
+                self.history[self.written & self.mask] = *sample;
+                if self.written.is_multiple_of(half) {
+                    self.start_grain(ratio);
+                }
+                self.written += 1;
+
+                let mut out = 0.0;
+                for slot in 0..self.grains.len() {
+                    let Some(grain) = self.grains[slot] else {
+                        continue;
+                    };
+                    let position = grain.centre + grain.ratio * (grain.age as f64 - half as f64);
+                    out += self.window[grain.age] * self.read(position);
+                    self.grains[slot] = (grain.age + 1 < self.grain).then_some(Grain {
+                        age: grain.age + 1,
+                        ..grain
+                    });
+                }
+                *sample = out;
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.history.fill(0.0);
+        self.written = 0;
+        self.grains = [None; 2];
+        let target = self.semitones.target();
+        self.semitones.reset(target);
+    }
+
+    fn latency(&self) -> usize {
+        self.delay
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pitch_detection::PitchDetector;
+    use crate::pitch_shifter::PITCH_FACTOR;
+
+    fn sine(freq: f32, len: usize) -> Vec<f32> {
+        (0..len)
+            .map(|i| 0.5 * (2.0 * PI * freq * i as f32 / 48_000.0).sin())
+            .collect()
+    }
+
+    fn fundamental(samples: &[f32]) -> f32 {
+        let mut detector = PitchDetector::new(48_000, 60.0, 1500.0);
+        detector.detect(samples).unwrap().frequency
+    }
+
+    fn rms(samples: &[f32]) -> f32 {
+        (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn stretches_time_and_pitch_independently() {
+        let input = sine(440.0, 48_000);
+        for &(time_ratio, pitch_ratio) in &[(1.5, 1.0), (0.75, 1.0), (1.0, 1.25), (1.5, 0.8)] {
+            let output = stretch(&input, 1, 48_000, time_ratio, pitch_ratio);
+            assert_eq!(output.len(), (48_000.0 * time_ratio) as usize);
+
+            let middle = &output[output.len() / 2 - 2048..output.len() / 2 + 2048];
+            let expected = 440.0 * pitch_ratio;
+            assert!(
+                (fundamental(middle) - expected).abs() < 2.0,
+                "{time_ratio} {pitch_ratio}"
+            );
+            assert!((rms(middle) / rms(&input) - 1.0).abs() < 0.1);
+        }
+    }
+
+    #[test]
+    fn shifts_live_input_without_changing_length() {
+        for &(factor, expected) in &[(1.0, 220.0), (1.5, 330.0), (0.5, 110.0), (6.0, 1320.0)] {
+            let mut shifter = WsolaShifter::new(Param::new(PITCH_FACTOR.with_default(factor)));
+            shifter.prepare(48_000, 1);
+            let mut block = sine(220.0, 24_000);
+            for chunk in block.chunks_mut(512) {
+                shifter.process(chunk);
+            }
+            let tail = &block[12_000..];
+            assert!(
+                (fundamental(tail) - expected).abs() < 2.0,
+                "factor {factor}"
+            );
+            assert!((rms(tail) / (0.5 / 2.0_f32.sqrt()) - 1.0).abs() < 0.2);
+        }
+    }
+}